@@ -0,0 +1,403 @@
+//! `logos_query!`, which parses a query when your crate is compiled
+//! instead of when it runs, so a typo in a query is a compile error:
+//!
+//! ```ignore
+//! #![feature(proc_macro)]
+//!
+//! extern crate logos;
+//! extern crate logos_macros;
+//!
+//! use logos_macros::logos_query;
+//!
+//! let name = "Bob";
+//! let query = logos_query!("find ?child where (?person name #name) (?child parent ?person)");
+//! ```
+//!
+//! The query is written as it would be for `parse_query`, except that
+//! `#name` stands for the value of the Rust variable `name`. An input
+//! can go anywhere a constant could, and it's converted with
+//! `Term::from`, so an entity must be an `Entity`, an attribute a
+//! `&str` and a value anything `Term<Value>` can be made from.
+
+#![feature(proc_macro)]
+
+extern crate logos;
+extern crate proc_macro;
+
+use logos::{BindForm, Call, Clause, Predicate, Query, Term, TuplePattern, Value, Var, ParseError,
+            parse_query};
+use logos::pull::{AttrSpec, PullAttr, PullPattern};
+use proc_macro::{Group, Span, TokenStream, TokenTree};
+
+/// Inputs are parsed as variables with this prefix, then turned back
+/// into Rust expressions.
+const INPUT_PREFIX: &str = "__logos_input_";
+
+#[proc_macro]
+pub fn logos_query(input: TokenStream) -> TokenStream {
+    match expand(&input.to_string()) {
+        Ok(code) => code.parse().unwrap(),
+        Err(msg) => {
+            // Point at the query rather than the whole macro call.
+            let span = input.into_iter().next().map_or_else(Span::call_site, |lit| lit.span());
+            let error: TokenStream = format!("compile_error!({:?})", msg).parse().unwrap();
+            error.into_iter().map(|token| respan(token, span)).collect()
+        }
+    }
+}
+
+/// `token` and everything in it, as if it were written at `span`.
+fn respan(token: TokenTree, span: Span) -> TokenTree {
+    let mut token = match token {
+        TokenTree::Group(group) => {
+            let stream = group.stream().into_iter().map(|t| respan(t, span)).collect();
+            TokenTree::Group(Group::new(group.delimiter(), stream))
+        }
+        other => other,
+    };
+    token.set_span(span);
+    token
+}
+
+/// The code for the query in the string literal `input`, or what's
+/// wrong with it.
+fn expand(input: &str) -> Result<String, String> {
+    let text = unquote(input.trim())
+        .ok_or_else(|| "logos_query! takes a single string literal".to_string())?;
+    let (marked, inputs) = mark_inputs(&text);
+
+    let query = parse_query(&marked)
+        .map_err(|err| unmark_error(&text, &inputs, err).to_string())?;
+    if let Some(var) = query.find.iter().find(|var| is_input(var)) {
+        return Err(format!("#{} is an input, so it can't be in the find spec",
+                           &var.name[INPUT_PREFIX.len()..]));
+    }
+    // Inputs only read by predicates and calls aren't bound by any
+    // clause, but they will have values.
+    let mut checked = query.clone();
+    checked.inputs.extend(query.predicates
+                              .iter()
+                              .flat_map(|p| p.vars())
+                              .chain(query.calls.iter().flat_map(|c| c.inputs()))
+                              .filter(|var| is_input(var))
+                              .cloned());
+    checked.validate().map_err(|err| err.to_string())?;
+
+    Ok(query_code(&query))
+}
+
+/// The contents of a string literal, which may be raw.
+fn unquote(lit: &str) -> Option<String> {
+    if lit.starts_with('r') {
+        let hashes = lit[1..].chars().take_while(|&c| c == '#').count();
+        if lit.len() < 2 * hashes + 3 || !lit[hashes + 1..].starts_with('"') ||
+           !lit[..lit.len() - hashes].ends_with('"') {
+            return None;
+        }
+        return Some(lit[hashes + 2..lit.len() - hashes - 1].to_string());
+    }
+
+    if lit.len() < 2 || !lit.starts_with('"') || !lit.ends_with('"') {
+        return None;
+    }
+
+    let mut text = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('u') => {
+                let hex = chars
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|&c| c != '}')
+                    .collect::<String>();
+                match u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32) {
+                    Some(c) => c,
+                    None => return None,
+                }
+            }
+            // A line continuation.
+            Some('\n') => {
+                let rest = chars.as_str().trim_left();
+                chars = rest.chars();
+                continue;
+            }
+            Some(c) => c,
+            None => return None,
+        };
+        text.push(escaped);
+    }
+    Some(text)
+}
+
+/// Rewrites each `#input` outside a string or comment as a variable,
+/// returning the new text and the 1-based line and column of each
+/// rewritten input.
+fn mark_inputs(text: &str) -> (String, Vec<(usize, usize)>) {
+    let mut marked = String::new();
+    let mut inputs = vec![];
+    let (mut line, mut column) = (1, 1);
+    let (mut in_string, mut in_comment) = (false, false);
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let starts_input = !in_string && !in_comment && c == '#' &&
+                           chars.peek().map_or(false, |&n| n.is_alphabetic() || n == '_');
+        if starts_input {
+            inputs.push((line, column));
+            marked.push('?');
+            marked.push_str(INPUT_PREFIX);
+            column += 1 + INPUT_PREFIX.len();
+            continue;
+        }
+
+        match c {
+            '"' if !in_comment => in_string = !in_string,
+            '\\' if in_string => {
+                marked.push(c);
+                column += 1;
+                if let Some(escaped) = chars.next() {
+                    marked.push(escaped);
+                    column += 1;
+                }
+                continue;
+            }
+            ';' if !in_string => in_comment = true,
+            '\n' => {
+                in_comment = false;
+                line += 1;
+                column = 0;
+            }
+            _ => {}
+        }
+        marked.push(c);
+        column += 1;
+    }
+
+    (marked, inputs)
+}
+
+/// Moves an error found in the marked text back to where it is in the
+/// text that was written.
+fn unmark_error(text: &str, inputs: &[(usize, usize)], err: ParseError) -> ParseError {
+    let shift = inputs
+        .iter()
+        .filter(|&&(line, column)| line == err.line && column < err.column)
+        .count() * INPUT_PREFIX.len();
+    let column = err.column.saturating_sub(shift).max(1);
+
+    ParseError::at(text, err.line, column, err.unexpected, err.expected)
+}
+
+fn is_input(var: &Var) -> bool {
+    var.name.starts_with(INPUT_PREFIX)
+}
+
+fn query_code(query: &Query) -> String {
+    let mut code = format!("{{ let mut query = ::logos::Query::new({}, {});",
+                           list(query.find.iter().map(var_code)),
+                           list(query.clauses.iter().map(clause_code)));
+    if !query.inputs.is_empty() {
+        code.push_str(&format!(" query.inputs = {};", list(query.inputs.iter().map(var_code))));
+    }
+    if !query.sources.is_empty() {
+        code.push_str(&format!(" query.sources = {};",
+                               list(query.sources.iter().map(|s| format!("{:?}.to_string()", s)))));
+    }
+    if !query.patterns.is_empty() {
+        code.push_str(&format!(" query.patterns = {};",
+                               list(query.patterns.iter().map(tuple_pattern_code))));
+    }
+    if !query.predicates.is_empty() {
+        code.push_str(&format!(" query.predicates = {};",
+                               list(query.predicates.iter().map(predicate_code))));
+    }
+    if !query.calls.is_empty() {
+        code.push_str(&format!(" query.calls = {};", list(query.calls.iter().map(call_code))));
+    }
+    for (var, pattern) in &query.pull {
+        code.push_str(&format!(" query.pull.insert({}, {});", var_code(var), pattern_code(pattern)));
+    }
+    code.push_str(" query }");
+    code
+}
+
+fn list<I: Iterator<Item = String>>(items: I) -> String {
+    format!("vec![{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+fn option<T, F: Fn(&T) -> String>(x: &Option<T>, code: F) -> String {
+    match *x {
+        Some(ref x) => format!("::std::option::Option::Some({})", code(x)),
+        None => "::std::option::Option::None".to_string(),
+    }
+}
+
+fn var_code(var: &Var) -> String {
+    format!("::logos::Var::new({:?})", var.name)
+}
+
+fn term_code<T, F: Fn(&T) -> String>(term: &Term<T>, bound: F) -> String {
+    match *term {
+        Term::Unbound(ref var) if is_input(var) => {
+            format!("::logos::Term::from(::std::clone::Clone::clone(&{}))",
+                    &var.name[INPUT_PREFIX.len()..])
+        }
+        Term::Unbound(ref var) => format!("::logos::Term::Unbound({})", var_code(var)),
+        Term::Bound(ref x) => format!("::logos::Term::Bound({})", bound(x)),
+    }
+}
+
+fn clause_code(clause: &Clause) -> String {
+    let entity = |e: &logos::Entity| format!("::logos::Entity({})", e.0);
+    let string = |s: &String| format!("{:?}.to_string()", s);
+
+    format!("::logos::Clause {{ entity: {}, attribute: {}, value: {}, tx: {}, added: {}, \
+             source: {} }}",
+            term_code(&clause.entity, &entity),
+            term_code(&clause.attribute, &string),
+            term_code(&clause.value, value_code),
+            option(&clause.tx, |tx| term_code(tx, &entity)),
+            option(&clause.added, |added| term_code(added, value_code)),
+            option(&clause.source, &string))
+}
+
+fn tuple_pattern_code(pattern: &TuplePattern) -> String {
+    format!("::logos::TuplePattern {{ source: {:?}.to_string(), terms: {} }}",
+            pattern.source,
+            list(pattern.terms.iter().map(|term| term_code(term, value_code))))
+}
+
+fn predicate_code(predicate: &Predicate) -> String {
+    format!("::logos::Predicate {{ op: ::logos::Comparison::{:?}, left: {}, right: {} }}",
+            predicate.op,
+            term_code(&predicate.left, value_code),
+            term_code(&predicate.right, value_code))
+}
+
+fn call_code(call: &Call) -> String {
+    format!("::logos::Call {{ function: ::logos::Function::{:?}, args: {}, bind: {} }}",
+            call.function,
+            list(call.args.iter().map(|arg| term_code(arg, value_code))),
+            option(&call.bind, bind_code))
+}
+
+fn bind_code(bind: &BindForm) -> String {
+    let vars = |vars: &Vec<Var>| list(vars.iter().map(var_code));
+    match *bind {
+        BindForm::Scalar(ref var) => format!("::logos::BindForm::Scalar({})", var_code(var)),
+        BindForm::Tuple(ref vs) => format!("::logos::BindForm::Tuple({})", vars(vs)),
+        BindForm::Collection(ref var) => {
+            format!("::logos::BindForm::Collection({})", var_code(var))
+        }
+        BindForm::Relation(ref vs) => format!("::logos::BindForm::Relation({})", vars(vs)),
+    }
+}
+
+fn value_code(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => format!("::logos::Value::String({:?}.to_string())", s),
+        Value::Ident(ref s) => format!("::logos::Value::Ident({:?}.to_string())", s),
+        Value::Entity(e) => format!("::logos::Value::Entity(::logos::Entity({}))", e.0),
+        Value::Boolean(b) => format!("::logos::Value::Boolean({})", b),
+        Value::Integer(n) => format!("::logos::Value::Integer({}i64)", n),
+        Value::Float(ref x) => format!("::logos::Value::Float(::logos::Float({:?}f64))", x.0),
+        Value::List(ref vs) => format!("::logos::Value::List({})", list(vs.iter().map(value_code))),
+        Value::Timestamp(_) | Value::Map(_) => {
+            unreachable!("queries can't contain {:?}", value)
+        }
+    }
+}
+
+fn pattern_code(pattern: &PullPattern) -> String {
+    format!("::logos::PullPattern({})", list(pattern.0.iter().map(attr_code)))
+}
+
+fn attr_code(attr: &PullAttr) -> String {
+    match *attr {
+        PullAttr::Wildcard => "::logos::PullAttr::Wildcard".to_string(),
+        PullAttr::Attr(AttrSpec { ref ident, reverse, ref limit, ref default, ref pattern }) => {
+            format!("::logos::PullAttr::Attr(::logos::AttrSpec {{ ident: {:?}.to_string(), \
+                     reverse: {}, limit: {}, default: {}, pattern: {} }})",
+                    ident,
+                    reverse,
+                    option(limit, |n| format!("{}usize", n)),
+                    option(default, value_code),
+                    option(pattern, pattern_code))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote(r#""find ?a where (?a name \"Bob\")""#),
+                   Some("find ?a where (?a name \"Bob\")".to_string()));
+        assert_eq!(unquote(r###"r#"(?a name "Bob")"#"###), Some("(?a name \"Bob\")".to_string()));
+        assert_eq!(unquote("\"caf\\u{e9}\""), Some("café".to_string()));
+        assert_eq!(unquote("find"), None);
+    }
+
+    #[test]
+    fn test_mark_inputs() {
+        let (marked, inputs) = mark_inputs("find ?a where (?a name #name) ; #not_this\n\
+                                            (?a tag \"#nor_this\")");
+        assert_eq!(marked,
+                   "find ?a where (?a name ?__logos_input_name) ; #not_this\n\
+                    (?a tag \"#nor_this\")");
+        assert_eq!(inputs, vec![(1, 24)]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let code = expand(r#""find ?a where (?a name #name) (?a parent 0)""#).unwrap();
+        assert!(code.contains("::logos::Term::from(::std::clone::Clone::clone(&name))"));
+        assert!(code.contains("::logos::Value::Integer(0i64)"));
+
+        let code = expand(r#""find ?a in $old where $old (?a name #name)""#).unwrap();
+        assert!(code.contains("query.sources = vec![\"old\".to_string()]"));
+        assert!(code.contains("source: ::std::option::Option::Some(\"old\".to_string())"));
+
+        let code = expand(r#""find ?n in $r where $r [?n 3]""#).unwrap();
+        assert!(code.contains("::logos::TuplePattern { source: \"r\".to_string(), terms: vec![\
+                               ::logos::Term::Unbound(::logos::Var::new(\"n\"))"));
+
+        let code = expand(r#""find ?a where (?a age ?n) [(>= ?n #min)]""#).unwrap();
+        assert!(code.contains("::logos::Predicate { op: ::logos::Comparison::Ge, \
+                               left: ::logos::Term::Unbound(::logos::Var::new(\"n\")), \
+                               right: ::logos::Term::from(::std::clone::Clone::clone(&min)) }"));
+
+        let code = expand(r#""find ?a where (?p name ?n) [(get-else ?p age #age) ?a]""#).unwrap();
+        assert!(code.contains("::logos::Call { function: ::logos::Function::GetElse"));
+        assert!(code.contains("bind: ::std::option::Option::Some(\
+                               ::logos::BindForm::Scalar(::logos::Var::new(\"a\")))"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = expand(r#""find ?a wehre (?a name #name)""#).unwrap_err();
+        assert!(err.contains("line 1, column 9"), "{}", err);
+        assert!(err.contains("did you mean `where`?"), "{}", err);
+
+        // The error is reported where it is in the query as written.
+        let err = expand(r#""find ?a where (?a #attr #name ?b ?c ?d)""#).unwrap_err();
+        assert!(err.contains("column 37"), "{}", err);
+
+        assert!(expand(r#""find ?b where (?a name #name)""#).unwrap_err().contains("?b"));
+        assert!(expand(r#""find #name where (?a name #name)""#)
+                    .unwrap_err()
+                    .contains("#name is an input"));
+        assert!(expand("find").is_err());
+    }
+}
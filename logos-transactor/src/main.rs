@@ -9,7 +9,7 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 
 use logos::db::{store_from_uri, TxClient};
-use logos::Tx;
+use logos::{Tx, TxReport};
 use logos::tx::Transactor;
 
 use rmp_serde::{Serializer, Deserializer};
@@ -52,9 +52,14 @@ fn main() {
     loop {
         let msg = socket.recv_msg(0).unwrap();
         let mut de = Deserializer::new(&msg[..]);
-        let tx: Tx = Deserialize::deserialize(&mut de).unwrap();
+        let tx: Result<Tx, _> = Deserialize::deserialize(&mut de);
         println!("Message: {:?}", tx);
-        let report = transactor.process_tx(tx).unwrap();
+        // Report failures back to the client rather than taking the
+        // transactor down.
+        let report = match tx {
+            Ok(tx) => transactor.process_tx(tx).unwrap_or_else(TxReport::Failure),
+            Err(e) => TxReport::Failure(e.into()),
+        };
 
         let mut msg_buf = Vec::new();
         report.serialize(&mut Serializer::new(&mut msg_buf)).unwrap();
@@ -1,4 +1,4 @@
-use {KVStore, Result};
+use {Error, KVStore, Result};
 
 use cdrs::connection_manager::ConnectionManager;
 use cdrs::query::QueryBuilder;
@@ -7,8 +7,27 @@ use cdrs::authenticators::NoneAuthenticator;
 use cdrs::transport::TransportTcp;
 use cdrs::types::ByName;
 use cdrs::types::value::{Value, Bytes};
+use cdrs::error;
 use r2d2;
 
+impl From<error::Error> for Error {
+    fn from(err: error::Error) -> Error {
+        Error::Storage(err.to_string())
+    }
+}
+
+impl From<r2d2::InitializationError> for Error {
+    fn from(err: r2d2::InitializationError) -> Error {
+        Error::Storage(err.to_string())
+    }
+}
+
+impl From<r2d2::GetTimeout> for Error {
+    fn from(err: r2d2::GetTimeout) -> Error {
+        Error::Storage(err.to_string())
+    }
+}
+
 #[derive(Clone)]
 pub struct CassandraStore {
     pool: r2d2::Pool<ConnectionManager<NoneAuthenticator, TransportTcp>>,
@@ -52,12 +71,11 @@ impl KVStore for CassandraStore {
                   .map(|b| b.into_rows()) {
             Ok(Some(rows)) => {
                 let v: Vec<u8> = rows.get(0)
-                    .ok_or("no rows found")?
-                    .r_by_name("val")
-                    .unwrap();
+                    .ok_or(Error::Storage(format!("no value for key {}", key)))?
+                    .r_by_name("val")?;
                 Ok(v)
             }
-            Ok(None) => Err("node not found".into()),
+            Ok(None) => Err(Error::Storage(format!("no value for key {}", key))),
             Err(e) => Err(e.into()),
         }
     }
@@ -5,7 +5,7 @@ use std::fmt::Debug;
 use super::{KVStore};
 use db::TxClient;
 use tx;
-use Result;
+use {Error, Result};
 
 // HashMap pretending to be a database
 #[derive(Clone, Debug)]
@@ -27,7 +27,9 @@ impl HeapStore {
 
 impl KVStore for HeapStore {
     fn set(&self, key: &str, value: &[u8]) -> Result<()> {
-        let mut guard = self.inner.lock()?;
+        let mut guard = self.inner
+            .lock()
+            .map_err(|_| Error::Storage("heap store lock poisoned".into()))?;
 
         match (*guard).insert(key.to_string(), value.to_vec()) {
             Some(_) => Ok(()),
@@ -38,9 +40,47 @@ impl KVStore for HeapStore {
     fn get(&self, key: &str) -> Result<Vec<u8>> {
         self.inner
             .lock()
-            .unwrap()
+            .map_err(|_| Error::Storage("heap store lock poisoned".into()))?
             .get(key)
             .map(|v| v.clone())
-            .ok_or("invalid reference".into())
+            .ok_or(Error::Storage(format!("no value for key {}", key)))
+    }
+}
+
+/// Keeps what's written to it in memory, reading anything it doesn't
+/// have from the store underneath, which is never written to.
+pub struct OverlayStore {
+    base: Arc<KVStore>,
+    writes: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl OverlayStore {
+    pub fn new(base: Arc<KVStore>) -> OverlayStore {
+        OverlayStore {
+            base,
+            writes: Mutex::new(HashMap::default()),
+        }
+    }
+}
+
+impl KVStore for OverlayStore {
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.writes
+            .lock()
+            .map_err(|_| Error::Storage("overlay store lock poisoned".into()))?
+            .insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let written = self.writes
+            .lock()
+            .map_err(|_| Error::Storage("overlay store lock poisoned".into()))?
+            .get(key)
+            .cloned();
+        match written {
+            Some(value) => Ok(value),
+            None => self.base.get(key),
+        }
     }
 }
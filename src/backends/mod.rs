@@ -1,5 +1,7 @@
+#[cfg(feature = "stores")]
 pub mod sqlite;
 pub mod mem;
+#[cfg(feature = "stores")]
 pub mod cassandra;
 
 use rmp_serde::{Serializer, Deserializer};
@@ -3,9 +3,15 @@ use std::sync::Arc;
 
 use rusqlite as sql;
 
-use Result;
+use {Error, Result};
 use super::KVStore;
 
+impl From<sql::Error> for Error {
+    fn from(err: sql::Error) -> Error {
+        Error::Storage(err.to_string())
+    }
+}
+
 #[derive(Clone)]
 pub struct SqliteStore {
     conn: Arc<sql::Connection>,
@@ -28,8 +34,7 @@ impl SqliteStore {
 impl KVStore for SqliteStore {
     fn get(&self, key: &str) -> Result<Vec<u8>> {
         let mut stmt = self.conn
-            .prepare("SELECT val FROM logos_kvs WHERE key = ?1")
-            .unwrap();
+            .prepare("SELECT val FROM logos_kvs WHERE key = ?1")?;
         stmt.query_row(&[&key], |row| {
                 let s: Vec<u8> = row.get(0);
                 s
@@ -39,8 +44,7 @@ impl KVStore for SqliteStore {
     fn set(&self, key: &str, value: &[u8]) -> Result<()> {
         let mut stmt = self.conn
             // We can't assume the key isn't already set, so need INSERT OR REPLACE.
-            .prepare("INSERT OR REPLACE INTO logos_kvs (key, val) VALUES (?1, ?2)")
-            .unwrap();
+            .prepare("INSERT OR REPLACE INTO logos_kvs (key, val) VALUES (?1, ?2)")?;
         stmt.execute(&[&key, &value])?;
         Ok(())
     }
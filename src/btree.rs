@@ -1,14 +1,15 @@
 use serde::{Serialize, Deserialize};
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{Bound, HashMap};
 use std::fmt::Debug;
 use std::ops::RangeFrom;
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use backends::KVStore;
 use db;
-use Result;
+use {Error, Result};
 
 pub const CAPACITY: usize = 512;
 
@@ -49,6 +50,16 @@ impl<'de, T, C> Index<T, C>
         }
     }
 
+    /// A new index with no items, whose nodes go in `store`.
+    pub fn empty(store: NodeStore<T>, comparator: C) -> Result<Self> {
+        let root_ref = store.add_node(IndexNode::Leaf { items: vec![] })?;
+        Ok(Index::new(root_ref, store, comparator))
+    }
+
+    pub fn node_store(&self) -> NodeStore<T> {
+        self.store.clone()
+    }
+
     pub fn insert(&self, item: T) -> Result<Index<T, C>> {
         let new_root =
             self.store
@@ -100,6 +111,18 @@ impl<'de, T, C> Index<T, C>
         }
     }
 
+    /// Node cache statistics for this index's node store, which is
+    /// shared with the other indexes of the same `Db`.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.store.stats()
+    }
+
+    /// The same index, counting each node read through it or the
+    /// indexes made from it in `reads`.
+    pub fn counting_reads(&self, reads: Arc<AtomicUsize>) -> Index<T, C> {
+        Index { store: self.store.counting(reads), ..self.clone() }
+    }
+
     pub fn iter(&self) -> Iter<T> {
         Iter {
             phantom: PhantomData,
@@ -114,6 +137,15 @@ impl<'de, T, C> Index<T, C>
         }
     }
 
+    /// The items from `start` up to `end`.
+    pub fn iter_range(&self, start: T, end: Bound<T>) -> Result<Range<T, C>> {
+        Ok(Range {
+               iter: self.iter_range_from(start..)?,
+               end,
+               comparator: PhantomData,
+           })
+    }
+
     // FIXME: Better would be to have this return either the Iter or,
     // if the store causes an error, to yield the error as the first iterator item.
     pub fn iter_range_from(&self, range: RangeFrom<T>) -> Result<Iter<T>> {
@@ -407,12 +439,73 @@ impl<'de, T: Debug + Ord + Clone + Deserialize<'de>> Iterator for Iter<T> {
     }
 }
 
+/// An `Iter` that stops at the end of a range.
+pub struct Range<T: Ord + Debug + Clone, C> {
+    iter: Iter<T>,
+    end: Bound<T>,
+    comparator: PhantomData<C>,
+}
+
+impl<'de, T, C> Iterator for Range<T, C>
+    where T: Debug + Ord + Clone + Deserialize<'de>,
+          C: Comparator<Item = T>
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match self.iter.next() {
+            Some(Ok(item)) => item,
+            other => return other,
+        };
+
+        let past_end = match self.end {
+            Bound::Included(ref end) => C::compare(&item, end) == Ordering::Greater,
+            Bound::Excluded(ref end) => C::compare(&item, end) != Ordering::Less,
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.iter.stack.clear();
+            None
+        } else {
+            Some(Ok(item))
+        }
+    }
+}
+
 // FIXME: NodeStore is an awkward solution which could probably be avoided with
 // lifetimes on Iter references to a KVStore
 #[derive(Clone)]
 pub struct NodeStore<T> {
     pub backing_store: Arc<KVStore + 'static>,
     cache: Arc<Mutex<HashMap<String, IndexNode<T>>>>,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+    /// Where reads through this handle are counted, apart from the
+    /// stats shared by every handle on the store.
+    reads: Option<Arc<AtomicUsize>>,
+}
+
+/// How many node reads have been served from a `NodeStore`'s cache
+/// and how many went to the backing store. Every read of a B-tree
+/// node is one or the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl CacheStats {
+    /// The reads counted in `self` but not in the earlier `before`.
+    pub fn since(&self, before: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits - before.hits,
+            misses: self.misses - before.misses,
+        }
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.hits + self.misses
+    }
 }
 
 impl<T: Debug> NodeStore<T> {
@@ -429,17 +522,51 @@ impl<T: Debug> NodeStore<T> {
     fn get_node<'de>(&self, key: &str) -> Result<IndexNode<T>>
         where T: Deserialize<'de> + Clone
     {
-        let store = &self.backing_store;
-        let mut hm = self.cache.lock().unwrap();
-        let node = hm.entry(key.to_string())
-            .or_insert_with(|| db::get_node(&(**store), key).unwrap());
-        Ok(node.clone())
+        if let Some(ref reads) = self.reads {
+            reads.fetch_add(1, AtomicOrdering::Relaxed);
+        }
+        let mut hm = self.cache
+            .lock()
+            .map_err(|_| Error::Storage("node cache lock poisoned".into()))?;
+        if let Some(node) = hm.get(key) {
+            self.hits.fetch_add(1, AtomicOrdering::Relaxed);
+            return Ok(node.clone());
+        }
+        self.misses.fetch_add(1, AtomicOrdering::Relaxed);
+
+        // Only cache nodes that were read successfully, so a failed
+        // read can be retried.
+        let node: IndexNode<T> = db::get_node(&(*self.backing_store), key)?;
+        hm.insert(key.to_string(), node.clone());
+        Ok(node)
     }
 
     pub fn new(store: Arc<KVStore>) -> NodeStore<T> {
         NodeStore {
             backing_store: store,
             cache: Arc::new(Mutex::new(HashMap::default())),
+            hits: Arc::new(AtomicUsize::new(0)),
+            misses: Arc::new(AtomicUsize::new(0)),
+            reads: None,
+        }
+    }
+
+    /// A handle on the same store that also counts each node read
+    /// through it in `reads`.
+    pub fn counting(&self, reads: Arc<AtomicUsize>) -> NodeStore<T> {
+        NodeStore {
+            backing_store: self.backing_store.clone(),
+            cache: self.cache.clone(),
+            hits: self.hits.clone(),
+            misses: self.misses.clone(),
+            reads: Some(reads),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(AtomicOrdering::Relaxed),
+            misses: self.misses.load(AtomicOrdering::Relaxed),
         }
     }
 }
@@ -510,6 +637,40 @@ mod tests {
                      range.start..full_range.end);
     }
 
+    #[test]
+    fn test_bounded_range_iter() {
+        let mut idx = test_idx();
+        for i in 0u64..10_000 {
+            idx = idx.insert(i).unwrap();
+        }
+
+        let range = |end| idx.iter_range(1457, end).unwrap().map(|item| item.unwrap());
+        assert_equal(range(Bound::Excluded(8000)), 1457u64..8000);
+        assert_equal(range(Bound::Included(8000)), 1457u64..8001);
+        assert_equal(range(Bound::Unbounded), 1457u64..10_000);
+        assert_eq!(range(Bound::Excluded(1457)).count(), 0);
+    }
+
+    #[test]
+    fn test_counting_reads() {
+        let mut idx = test_idx();
+        for i in 0u64..2000 {
+            idx = idx.insert(i).unwrap();
+        }
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counted = idx.counting_reads(reads.clone());
+        let before = idx.cache_stats();
+        assert_eq!(counted.iter().count(), 2000);
+        let nodes = idx.cache_stats().since(before).nodes();
+        assert!(nodes > 0);
+        assert_eq!(reads.load(AtomicOrdering::SeqCst), nodes);
+
+        // Reads through the original index aren't counted.
+        assert_eq!(idx.iter().count(), 2000);
+        assert_eq!(reads.load(AtomicOrdering::SeqCst), nodes);
+    }
+
     #[bench]
     fn bench_insert_sequence(b: &mut Bencher) {
         let mut tree = test_idx();
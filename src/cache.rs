@@ -0,0 +1,210 @@
+//! A cache of query results that can be shared by every `Db` from a
+//! `Conn`, or from several. A `Db` never changes, so the result of a
+//! query depends only on the index roots and view of the `Db`, the
+//! query and its inputs.
+
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+use db::{Db, View};
+use query::{Query, Var};
+use {Binding, Error, QueryResult, Result, Value};
+
+/// Counts of what the cache has done since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheMetrics {
+    pub hits: usize,
+    pub misses: usize,
+    /// Results dropped to keep the cache within its capacity.
+    pub evictions: usize,
+    /// The number of results in the cache now.
+    pub entries: usize,
+}
+
+/// Holds up to `capacity` query results, evicting the least recently
+/// used when it's full.
+pub struct ResultCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+/// What a `Db` reads: its index roots and view.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Basis {
+    eav: String,
+    ave: String,
+    aev: String,
+    view: View,
+}
+
+/// Where a result is cached: the basis it was computed from, and a
+/// hash of the query and its inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    basis: Basis,
+    hash: u64,
+}
+
+struct Entry {
+    /// The query and inputs are kept to tell apart any that hash the
+    /// same.
+    query: Query,
+    inputs: Vec<(Var, Value)>,
+    result: QueryResult,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// The key of each entry by when it was last used, oldest first.
+    recency: BTreeMap<u64, Key>,
+    /// Incremented on every lookup and insertion, to order entries by
+    /// last use.
+    clock: u64,
+    metrics: CacheMetrics,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> ResultCache {
+        ResultCache {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        self.inner.lock().map(|inner| inner.metrics).unwrap_or_default()
+    }
+
+    /// The cached result of `query` with `inputs` against `db`, or the
+    /// result of `run`, which is cached if it succeeds.
+    pub(crate) fn get_or_run<F>(&self,
+                                db: &Db,
+                                query: &Query,
+                                inputs: &Binding,
+                                run: F)
+                                -> Result<QueryResult>
+        where F: FnOnce() -> Result<QueryResult>
+    {
+        let mut inputs = inputs
+            .iter()
+            .map(|(var, value)| (var.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        inputs.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        let key = Key {
+            basis: Basis {
+                eav: db.eav.root_ref.clone(),
+                ave: db.ave.root_ref.clone(),
+                aev: db.aev.root_ref.clone(),
+                view: db.view,
+            },
+            hash: hash(query, &inputs),
+        };
+
+        if let Some(result) = self.lookup(&key, query, &inputs)? {
+            return Ok(result);
+        }
+
+        // The lock isn't held while the query runs, so the same query
+        // may occasionally be run twice at once.
+        let result = run()?;
+        self.insert(key, query, inputs, &result)?;
+        Ok(result)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Inner>> {
+        self.inner
+            .lock()
+            .map_err(|_| Error::Storage("result cache lock poisoned".into()))
+    }
+
+    fn lookup(&self,
+              key: &Key,
+              query: &Query,
+              inputs: &[(Var, Value)])
+              -> Result<Option<QueryResult>> {
+        let mut inner = self.lock()?;
+        let inner = &mut *inner;
+        inner.clock += 1;
+        let now = inner.clock;
+
+        let found = match inner.entries.get_mut(key) {
+            Some(ref mut entry) if entry.query == *query && entry.inputs == inputs => {
+                inner.recency.remove(&entry.last_used);
+                inner.recency.insert(now, key.clone());
+                entry.last_used = now;
+                Some(entry.result.clone())
+            }
+            _ => None,
+        };
+
+        match found {
+            Some(_) => inner.metrics.hits += 1,
+            None => inner.metrics.misses += 1,
+        }
+        Ok(found)
+    }
+
+    fn insert(&self,
+              key: Key,
+              query: &Query,
+              inputs: Vec<(Var, Value)>,
+              result: &QueryResult)
+              -> Result<()> {
+        let mut inner = self.lock()?;
+        let inner = &mut *inner;
+        inner.clock += 1;
+        let now = inner.clock;
+
+        let entry = Entry {
+            query: query.clone(),
+            inputs,
+            result: result.clone(),
+            last_used: now,
+        };
+        // Replaces the entry for the same key, if another run of the
+        // query got there first or a different one hashed the same.
+        if let Some(old) = inner.entries.insert(key.clone(), entry) {
+            inner.recency.remove(&old.last_used);
+        }
+        inner.recency.insert(now, key);
+
+        while inner.entries.len() > self.capacity {
+            let oldest = match inner.recency.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(key) = inner.recency.remove(&oldest) {
+                inner.entries.remove(&key);
+            }
+            inner.metrics.evictions += 1;
+        }
+        inner.metrics.entries = inner.entries.len();
+        Ok(())
+    }
+}
+
+/// A hash of `query` and `inputs`. Neither is `Hash`, since floats
+/// aren't, so their debug forms are hashed, with the pull patterns in
+/// order of their variables.
+fn hash(query: &Query, inputs: &[(Var, Value)]) -> u64 {
+    let mut pull = query.pull.iter().collect::<Vec<_>>();
+    pull.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    let mut hasher = DefaultHasher::new();
+    format!("{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            query.find,
+            query.clauses,
+            query.predicates,
+            query.calls,
+            pull,
+            query.rules,
+            query.goals,
+            query.inputs,
+            query.sources,
+            inputs)
+            .hash(&mut hasher);
+    hasher.finish()
+}
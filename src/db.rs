@@ -1,10 +1,18 @@
 use super::*;
+use std::cmp::Ordering;
+use std::collections::Bound;
 use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
+use std::{f64, u64};
 
+use backends::mem::OverlayStore;
 use btree::IndexNode;
-use tx::Transactor;
+use limits::Limits;
+use prepared::ClausePlan;
+use query::{Comparison, Predicate};
+use tx::{self, Transactor};
 
+use chrono::prelude::{DateTime, UTC};
 use rmp_serde::{Serializer, Deserializer};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
@@ -25,12 +33,28 @@ lazy_static! {
 pub struct Conn {
     transactor: TxClient,
     store: Arc<KVStore>,
+    cache: Option<Arc<ResultCache>>,
 }
 
 impl Conn {
     pub fn new(store: Arc<KVStore>) -> Result<Conn> {
         let transactor = store.get_transactor()?;
-        Ok(Conn { transactor, store })
+        Ok(Conn {
+               transactor,
+               store,
+               cache: None,
+           })
+    }
+
+    /// Memoizes the results of queries on every `Db` from this
+    /// connection in `cache`, which can be shared with other
+    /// connections.
+    pub fn with_result_cache(self, cache: Arc<ResultCache>) -> Conn {
+        Conn { cache: Some(cache), ..self }
+    }
+
+    pub fn result_cache(&self) -> Option<&Arc<ResultCache>> {
+        self.cache.as_ref()
     }
 }
 
@@ -38,35 +62,18 @@ impl Conn {
     pub fn db(&self) -> Result<Db> {
         let contents: DbContents = self.store.get_contents()?;
 
-        let node_store = btree::NodeStore::new(self.store.clone());
-        Ok(Db {
-            store: self.store.clone(),
-            idents: contents.idents,
-            eav: Index::new(contents.eav, node_store.clone(), EAVT),
-            ave: Index::new(contents.ave, node_store.clone(), AVET),
-            aev: Index::new(contents.aev, node_store, AEVT),
-        })
+        let mut db = Db::new(contents, self.store.clone());
+        db.cache = self.cache.clone();
+        Ok(db)
     }
 
     pub fn transact(&self, tx: Tx) -> Result<TxReport> {
         match self.transactor {
-            // TODO: Don't ignore the addr here.
-            TxClient::Network(_) => {
-                let mut msg_buf: Vec<u8> = Vec::new();
-                tx.serialize(&mut Serializer::new(&mut msg_buf))?;
-                let ctx = zmq::Context::new();
-                let socket = ctx.socket(zmq::REQ)?;
-                socket.connect("tcp://localhost:10405")?;
-                socket.send_msg(zmq::Message::from_slice(&msg_buf)?, 0)?;
-
-                let result = socket.recv_msg(0)?;
-                let mut de = Deserializer::new(&result[..]);
-                let report: TxReport = Deserialize::deserialize(&mut de)?;
-                Ok(report)
-            }
+            TxClient::Network(addr) => send_tx(addr, tx),
             TxClient::Local => {
                 let store = self.store.clone();
-                let _ = TX_LOCK.lock()?;
+                let _guard = TX_LOCK.lock()
+                    .map_err(|_| Error::Transactor("transaction lock poisoned".into()))?;
                 let mut transactor = Transactor::new(store)?;
                 transactor.process_tx(tx)
             }
@@ -74,14 +81,82 @@ impl Conn {
     }
 }
 
+// TODO: Don't ignore the addr here.
+#[cfg(feature = "network")]
+fn send_tx(_addr: SocketAddr, tx: Tx) -> Result<TxReport> {
+    let mut msg_buf: Vec<u8> = Vec::new();
+    tx.serialize(&mut Serializer::new(&mut msg_buf))?;
+    let ctx = zmq::Context::new();
+    let socket = ctx.socket(zmq::REQ)?;
+    socket.connect("tcp://localhost:10405")?;
+    socket.send_msg(zmq::Message::from_slice(&msg_buf)?, 0)?;
+
+    let result = socket.recv_msg(0)?;
+    let mut de = Deserializer::new(&result[..]);
+    let report: TxReport = Deserialize::deserialize(&mut de)?;
+    Ok(report)
+}
+
+#[cfg(not(feature = "network"))]
+fn send_tx(addr: SocketAddr, _tx: Tx) -> Result<TxReport> {
+    Err(Error::Transactor(format!("can't reach the transactor at {}: logos was built without \
+                                   the `network` feature",
+                                  addr)))
+}
+
+/// A point in the database's timeline, given either as the entity
+/// of a transaction or as a wall-clock time, which is resolved to the
+/// latest transaction whose `db:txInstant` is not after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxPoint {
+    Tx(Entity),
+    Instant(DateTime<UTC>),
+}
+
+impl From<Entity> for TxPoint {
+    fn from(tx: Entity) -> Self {
+        TxPoint::Tx(tx)
+    }
+}
+
+impl From<DateTime<UTC>> for TxPoint {
+    fn from(instant: DateTime<UTC>) -> Self {
+        TxPoint::Instant(instant)
+    }
+}
+
+/// Restricts which records of the indexes are visible through a `Db`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct View {
+    /// Only records from transactions up to and including this one.
+    pub as_of: Option<Entity>,
+    /// Only records from transactions after this one.
+    pub since: Option<Entity>,
+    /// Show every record, retractions included, instead of the
+    /// current state of each fact.
+    pub history: bool,
+}
+
 /// An *immutable* view of the database at a point in time.
 /// Only used for querying; for transactions, you need a Conn.
+#[derive(Clone)]
 pub struct Db {
     pub idents: IdentMap,
     pub store: Arc<KVStore + 'static>,
     pub eav: Index<Record, EAVT>,
     pub ave: Index<Record, AVET>,
     pub aev: Index<Record, AEVT>,
+    /// The tokens of the string values of `db:fulltext` attributes,
+    /// each in a record of its own. It's only created once the first
+    /// such value is added.
+    pub fulltext: Option<Index<Record, AVET>>,
+    pub view: View,
+    /// The id the next new entity will get.
+    pub next_id: u64,
+    /// Where query results are memoized, if anywhere.
+    pub cache: Option<Arc<ResultCache>>,
+    /// The limits of the query being run, if it has any.
+    pub(crate) limits: Option<Arc<Limits>>,
 }
 
 impl Db {
@@ -92,110 +167,427 @@ impl Db {
             idents: contents.idents,
             eav: Index::new(contents.eav, node_store.clone(), EAVT),
             ave: Index::new(contents.ave, node_store.clone(), AVET),
-            aev: Index::new(contents.aev, node_store, AEVT),
+            aev: Index::new(contents.aev, node_store.clone(), AEVT),
+            fulltext: contents.fulltext.map(|root| Index::new(root, node_store, AVET)),
+            view: View::default(),
+            next_id: contents.next_id,
+            cache: None,
+            limits: None,
         };
 
         db
     }
 
-    fn records_matching(&self, clause: &Clause, binding: &Binding) -> Result<Vec<Record>> {
-        let expanded = clause.substitute(binding)?;
-        match expanded {
+    /// Applies `tx` to this database speculatively, without going
+    /// through the transactor.  The returned `Db` reflects the
+    /// transaction, but the database itself is unchanged: the new
+    /// index nodes are kept in memory, over the store they're read
+    /// from.
+    pub fn with(&self, tx: Tx) -> Result<(Db, TxReport)> {
+        let store: Arc<KVStore> = Arc::new(OverlayStore::new(self.store.clone()));
+        let node_store = btree::NodeStore::new(store.clone());
+        let speculative = Db {
+            store,
+            eav: Index::new(self.eav.root_ref.clone(), node_store.clone(), EAVT),
+            ave: Index::new(self.ave.root_ref.clone(), node_store.clone(), AVET),
+            aev: Index::new(self.aev.root_ref.clone(), node_store.clone(), AEVT),
+            fulltext: self.fulltext
+                .as_ref()
+                .map(|index| Index::new(index.root_ref.clone(), node_store.clone(), AVET)),
+            ..self.clone()
+        };
+        tx::apply(&speculative, tx)
+    }
+
+    /// The database as it was immediately after the transaction at `t`.
+    pub fn as_of<T: Into<TxPoint>>(&self, t: T) -> Result<Db> {
+        let tx = self.resolve_tx(t.into())?;
+        let mut db = self.clone();
+        db.view.as_of = Some(tx);
+        Ok(db)
+    }
+
+    /// Only the facts asserted or retracted by transactions after `t`.
+    pub fn since<T: Into<TxPoint>>(&self, t: T) -> Result<Db> {
+        let tx = self.resolve_tx(t.into())?;
+        let mut db = self.clone();
+        db.view.since = Some(tx);
+        Ok(db)
+    }
+
+    /// Every assertion and retraction ever made. Clauses can bind
+    /// whether each record was an addition with a fifth term, as in
+    /// `(?e name ?n ?tx ?op)`.
+    pub fn history(&self) -> Db {
+        let mut db = self.clone();
+        db.view.history = true;
+        db
+    }
+
+    fn resolve_tx(&self, t: TxPoint) -> Result<Entity> {
+        match t {
+            TxPoint::Tx(tx) => Ok(tx),
+            TxPoint::Instant(instant) => {
+                let attr = self.idents
+                    .get_entity("db:txInstant".to_string())
+                    .ok_or(Error::Schema("unknown attribute db:txInstant".into()))?;
+                let range_start = Record::addition(Entity(0), attr, Value::String("".into()), Entity(0));
+                let latest = Value::Timestamp(instant);
+                take_while_ok(self.ave.iter_range_from(range_start..)?,
+                              |rec| rec.attribute == attr && rec.value <= latest)?
+                    .last()
+                    .map(|rec| rec.entity)
+                    .ok_or(Error::Validation(format!("no transaction at or before {}", instant)))
+            }
+        }
+    }
+
+    /// Applies this db's view to a run of records read from one of
+    /// the indexes.
+    pub(crate) fn visible<I: Iterator<Item = Record>>(&self, records: I) -> Vec<Record> {
+        let View { as_of, since, history } = self.view;
+        let in_range = records.filter(|rec| {
+                                          as_of.map_or(true, |tx| rec.tx <= tx) &&
+                                          since.map_or(true, |tx| rec.tx > tx)
+                                      });
+        if history {
+            in_range.collect()
+        } else {
+            current(in_range)
+        }
+    }
+
+    /// The visible records of every attribute of `entity`.
+    pub(crate) fn entity_records(&self, entity: Entity) -> Result<Vec<Record>> {
+        let range_start = Record::addition(entity, Entity(0), Value::String("".into()), Entity(0));
+        let records = take_while_ok(self.eav.iter_range_from(range_start..)?,
+                                    |rec| rec.entity == entity)?;
+        Ok(self.visible(records.into_iter()))
+    }
+
+    /// The visible records of `attr`, for every entity.
+    pub(crate) fn attribute_records(&self, attr: Entity) -> Result<Vec<Record>> {
+        let range_start = Record::addition(Entity(0), attr, Value::String("".into()), Entity(0));
+        let records = take_while_ok(self.aev.iter_range_from(range_start..)?,
+                                    |rec| rec.attribute == attr)?;
+        Ok(self.visible(records.into_iter()))
+    }
+
+    /// The visible values of `attr` on `entity`.
+    pub(crate) fn attribute_values(&self, entity: Entity, attr: Entity) -> Result<Vec<Value>> {
+        let range_start = Record::addition(entity, attr, Value::String("".into()), Entity(0));
+        let records = take_while_ok(self.eav.iter_range_from(range_start..)?,
+                                    |rec| rec.entity == entity && rec.attribute == attr)?;
+        Ok(self.visible(records.into_iter())
+               .into_iter()
+               .map(|rec| rec.value)
+               .collect())
+    }
+
+    /// Entities which have `entity` as a value of `attr`, whether it's
+    /// stored as the entity, its id or its ident.
+    pub(crate) fn referrers(&self, entity: Entity, attr: Entity) -> Result<Vec<Entity>> {
+        let target = Value::Entity(entity);
+        let mut records = vec![];
+        for target in Some(target.clone()).into_iter().chain(self.idents.aliases(&target)) {
+            let range_start = Record::addition(Entity(0), attr, target.clone(), Entity(0));
+            records.extend(take_while_ok(self.ave.iter_range_from(range_start..)?,
+                                         |rec| rec.attribute == attr && rec.value == target)?);
+        }
+        Ok(self.visible(records.into_iter())
+               .into_iter()
+               .map(|rec| rec.entity)
+               .collect())
+    }
+
+    /// The records that match `clause` under `binding`. `plan` gives
+    /// the clause's attribute and index if they were worked out when
+    /// the query was prepared.
+    fn records_matching(&self,
+                        clause: &Clause,
+                        binding: &Binding,
+                        plan: Option<&ClausePlan>)
+                        -> Result<Vec<Record>> {
+        let expanded = clause.substitute(binding, &self.idents)?;
+        let attr = match (plan.and_then(|p| p.attribute), &expanded.attribute) {
+            (Some(attr), _) => Some(attr),
+            (None, &Term::Bound(ref a)) => Some(self.attribute_entity(a)?),
+            (None, &Term::Unbound(_)) => None,
+        };
+        let access = plan.map(|p| p.access)
+            .unwrap_or_else(|| {
+                                Access::choose(expanded.entity.var().is_none(),
+                                               attr.is_some(),
+                                               expanded.value.var().is_none())
+                            });
+
+        let records = match (access, &expanded.entity, attr, &expanded.value) {
             // ?e a v => use the ave index
-            Clause {
-                entity: Term::Unbound(_),
-                attribute: Term::Bound(a),
-                value: Term::Bound(v),
-            } => {
-                match self.idents.get_entity(a) {
-                    Some(attr) => {
-                        let range_start = Record::addition(Entity(0), attr, v.clone(), Entity(0));
-                        Ok(self.ave
-                               .iter_range_from(range_start..)?
-                               .map(|res| res.unwrap())
-                               .take_while(|rec| rec.attribute == attr && rec.value == v)
-                               .collect())
-                    }
-                    _ => return Err("invalid attribute".into()),
+            (Access::Avet, _, Some(attr), &Term::Bound(ref v)) => {
+                // A reference may be stored as the entity, its id or its
+                // ident, so look up each.
+                let mut records = vec![];
+                for v in Some(v.clone()).into_iter().chain(self.idents.aliases(v)) {
+                    let range_start = Record::addition(Entity(0), attr, v.clone(), Entity(0));
+                    records.extend(self.scan(self.ave.iter_range_from(range_start..)?,
+                                             |rec| rec.attribute == attr && rec.value == v)?);
                 }
+                records
             }
-            // // e a ?v => use the eav index
-            Clause {
-                entity: Term::Bound(e),
-                attribute: Term::Bound(a),
-                value: Term::Unbound(_),
-            } => {
-                match self.idents.get_entity(a) {
-                    Some(attr) => {
-                        // Value::String("") is the lowest-sorted value
-                        let range_start =
-                            Record::addition(e, attr, Value::String("".into()), Entity(0));
-                        Ok(self.eav
-                               .iter_range_from(range_start..)?
-                               .map(|res| res.unwrap())
-                               .take_while(|rec| rec.entity == e && rec.attribute == attr)
-                               .collect())
-                    }
-                    _ => return Err("invalid attribute".into()),
-                }
+            // ?e a ?v with predicates on ?v => scan part of the
+            // attribute in the ave index
+            (Access::AvetRange, _, Some(attr), &Term::Unbound(ref v)) => {
+                let range = plan.map_or(&[][..], |p| &p.range[..]);
+                let (start, end) = value_range(attr, v, range, binding);
+                self.scan(self.ave.iter_range(start, end)?, |_| true)?
+            }
+            // e a ?v => use the eav index
+            (Access::Eavt, &Term::Bound(e), Some(attr), _) => {
+                // Value::String("") is the lowest-sorted value
+                let range_start = Record::addition(e, attr, Value::String("".into()), Entity(0));
+                self.scan(self.eav.iter_range_from(range_start..)?,
+                          |rec| rec.entity == e && rec.attribute == attr)?
             }
-            // FIXME: Implement other optimized index use cases? (multiple unknowns? refs?)
+            // e ?a ?v or e ?a v => scan the entity in the eav index
+            (Access::Eavt, &Term::Bound(e), None, _) => {
+                let range_start = Record::addition(e, Entity(0), Value::String("".into()), Entity(0));
+                self.scan(self.eav.iter_range_from(range_start..)?, |rec| rec.entity == e)?
+            }
+            // ?e a ?v => scan the attribute in the aev index
+            (Access::Aevt, _, Some(attr), _) => {
+                let range_start = Record::addition(Entity(0), attr, Value::String("".into()), Entity(0));
+                self.scan(self.aev.iter_range_from(range_start..)?,
+                          |rec| rec.attribute == attr)?
+            }
+            // FIXME: Implement other optimized index use cases? (refs?)
             // Fallthrough case: just scan the EAV index. Correct but slow.
-            _ => {
-                Ok(self.eav
-                    .iter()
-                    .map(|f| f.unwrap()) // FIXME this is not safe :D
-                    .filter(|f| unify(&binding, &self.idents, &clause, &f).is_some())
-                    .collect())
+            _ => self.scan(self.eav.iter(), |_| true)?,
+        };
+
+        Ok(self.visible(records.into_iter())
+               .into_iter()
+               .filter(|f| unify(&binding, &self.idents, &clause, &f).is_some())
+               .collect())
+    }
+
+    /// Like `take_while_ok`, but checks the running query's limits as
+    /// it goes, so that a long scan can be aborted part way.
+    fn scan<I, P>(&self, records: I, mut pred: P) -> Result<Vec<Record>>
+        where I: Iterator<Item = Result<Record>>,
+              P: FnMut(&Record) -> bool
+    {
+        let mut result = vec![];
+        for rec in records {
+            let rec = rec?;
+            if !pred(&rec) {
+                break;
+            }
+            result.push(rec);
+            if result.len() % 1024 == 0 {
+                self.check_limits(0)?;
             }
         }
+        Ok(result)
     }
 
-    pub fn query(&self, query: &Query) -> Result<QueryResult> {
-        // TODO: automatically bind ?tx in queries
-        let mut bindings = vec![HashMap::new()];
-
-        for clause in &query.clauses {
-            let mut new_bindings = vec![];
-
-            for binding in bindings {
-                for record in self.records_matching(clause, &binding)? {
-                    match unify(&binding, &self.idents, clause, &record) {
-                        Some(new_info) => {
-                            if record.retracted {
-                                // The binding matches the retraction
-                                // so we discard any existing bindings
-                                // that are the same.  Note that this
-                                // relies on the fact that additions
-                                // and retractions are sorted by
-                                // transaction, so an older retraction
-                                // won't delete the binding for a
-                                // newer addition.
-                                new_bindings.retain(|b| *b != new_info);
-                            } else {
-                                new_bindings.push(new_info)
-                            }
-                        }
-                        _ => continue,
-                    }
+    /// The entity of the attribute with the given ident.
+    pub(crate) fn attribute_entity(&self, ident: &str) -> Result<Entity> {
+        self.idents
+            .get_entity(ident.to_string())
+            .ok_or_else(|| Error::Schema(format!("unknown attribute {}", ident)))
+    }
+
+    /// Extends each of `bindings` with every way of matching all of
+    /// `clauses`.
+    pub(crate) fn solve(&self,
+                        clauses: &[Clause],
+                        mut bindings: Vec<Binding>)
+                        -> Result<Vec<Binding>> {
+        for clause in clauses {
+            bindings = self.match_clause(clause, None, bindings)?;
+        }
+
+        Ok(bindings)
+    }
+
+    /// Extends each of `bindings` with every way of matching `clause`.
+    pub(crate) fn match_clause(&self,
+                               clause: &Clause,
+                               plan: Option<&ClausePlan>,
+                               bindings: Vec<Binding>)
+                               -> Result<Vec<Binding>> {
+        let mut new_bindings = vec![];
+
+        for binding in bindings {
+            self.check_limits(new_bindings.len())?;
+            for record in self.records_matching(clause, &binding, plan)? {
+                match unify(&binding, &self.idents, clause, &record) {
+                    Some(new_info) => new_bindings.push(new_info),
+                    _ => continue,
                 }
             }
-
-            bindings = new_bindings;
         }
+        self.check_limits(new_bindings.len())?;
 
+        Ok(new_bindings)
+    }
+
+    pub fn query(&self, query: &Query) -> Result<QueryResult> {
+        self.prepare(query)?.execute(self, &[])
+    }
+
+    /// Narrows each binding down to the query's find variables,
+    /// pulling any that the query pulls.
+    pub(crate) fn project(&self, query: &Query, mut bindings: Vec<Binding>) -> Result<QueryResult> {
         for binding in bindings.iter_mut() {
             *binding = binding
                 .iter()
                 .filter(|&(k, _)| query.find.contains(k))
                 .map(|(var, value)| (var.clone(), value.clone()))
                 .collect();
+
+            for (var, pattern) in &query.pull {
+                let pulled = match binding.get(var) {
+                    Some(val) => {
+                        match self.idents.resolve(val) {
+                            Some(e) => self.pull(pattern, e)?,
+                            None => {
+                                return Err(Error::Validation(format!("cannot pull ?{}, \
+                                                                      which is {}",
+                                                                     var.name,
+                                                                     val)))
+                            }
+                        }
+                    }
+                    None => continue,
+                };
+                binding.insert(var.clone(), pulled);
+            }
         }
 
         Ok(QueryResult(query.find.clone(), bindings))
     }
 }
 
+/// The part of the AVET index holding the values of `attr` that might
+/// pass each of `predicates` on `var`. Only their ordering is used, so
+/// the range can hold values that still fail them.
+fn value_range(attr: Entity,
+               var: &Var,
+               predicates: &[Predicate],
+               binding: &Binding)
+               -> (Record, Bound<Record>) {
+    let first = |v: Value| Record::addition(Entity(0), attr, v, Entity(0));
+    let last = |v: Value| {
+        Record { retracted: true, ..Record::addition(Entity(u64::MAX), attr, v, Entity(u64::MAX)) }
+    };
+
+    // Value::String("") is the lowest-sorted value
+    let mut start = first(Value::String("".into()));
+    let mut end = Bound::Excluded(first_of_next_attribute(attr));
+    for (op, other) in predicates.iter().filter_map(|p| p.bound_on(var)) {
+        let value = match *other {
+            Term::Bound(ref v) => v.clone(),
+            Term::Unbound(ref other) => {
+                match binding.get(other) {
+                    Some(v) => v.clone(),
+                    None => continue,
+                }
+            }
+        };
+
+        // Numbers are compared by value, so a bound on one has to take
+        // in numbers of every type that are equal to it.
+        let (lowest, highest) = match value.number() {
+            Some(Float(x)) => {
+                let next = next_float(x).map(|y| first(Value::Float(Float(y))));
+                (first(Value::Float(Float(x))),
+                 next.map_or(Bound::Excluded(first_of_next_attribute(attr)), Bound::Excluded))
+            }
+            None if op == Comparison::Lt => (first(value.clone()), Bound::Excluded(first(value))),
+            None => (first(value.clone()), Bound::Included(last(value))),
+        };
+
+        match op {
+            Comparison::Gt | Comparison::Ge => {
+                if AVET::compare(&lowest, &start) == Ordering::Greater {
+                    start = lowest;
+                }
+            }
+            _ => {
+                if is_before(&highest, &end) {
+                    end = highest;
+                }
+            }
+        }
+    }
+    (start, end)
+}
+
+/// The lowest record of the attribute after `attr`.
+fn first_of_next_attribute(attr: Entity) -> Record {
+    Record::addition(Entity(0), Entity(attr.0 + 1), Value::String("".into()), Entity(0))
+}
+
+/// Whether the range ending at `a` ends before the one ending at `b`.
+fn is_before(a: &Bound<Record>, b: &Bound<Record>) -> bool {
+    match (a, b) {
+        (_, &Bound::Unbounded) => true,
+        (&Bound::Unbounded, _) => false,
+        (&Bound::Excluded(ref a), &Bound::Included(ref b)) => {
+            AVET::compare(a, b) != Ordering::Greater
+        }
+        (&Bound::Included(ref a), &Bound::Included(ref b)) |
+        (&Bound::Included(ref a), &Bound::Excluded(ref b)) |
+        (&Bound::Excluded(ref a), &Bound::Excluded(ref b)) => AVET::compare(a, b) == Ordering::Less,
+    }
+}
+
+/// The smallest float greater than `x`, if there is one.
+fn next_float(x: f64) -> Option<f64> {
+    if x.is_nan() || x == f64::INFINITY {
+        None
+    } else if x == 0.0 {
+        Some(f64::from_bits(1))
+    } else if x > 0.0 {
+        Some(f64::from_bits(x.to_bits() + 1))
+    } else {
+        Some(f64::from_bits(x.to_bits() - 1))
+    }
+}
+
+/// Reads records from an index iterator for as long as `pred` holds,
+/// stopping at the first error from the store.
+pub(crate) fn take_while_ok<I, P>(records: I, mut pred: P) -> Result<Vec<Record>>
+    where I: Iterator<Item = Result<Record>>,
+          P: FnMut(&Record) -> bool
+{
+    let mut result = vec![];
+    for res in records {
+        let rec = res?;
+        if !pred(&rec) {
+            break;
+        }
+        result.push(rec);
+    }
+    Ok(result)
+}
+
+/// Collapses the history of each (e, a, v) triple down to its current
+/// state: the record with the greatest tx wins, and a triple whose
+/// latest record is a retraction is dropped entirely.  This relies on
+/// every index keeping the records for a triple adjacent and sorted
+/// by tx, with a retraction after an addition in the same tx.
+fn current<I: Iterator<Item = Record>>(records: I) -> Vec<Record> {
+    let history = records.group_by(|rec| (rec.entity, rec.attribute, rec.value.clone()));
+    let result = history
+        .into_iter()
+        .filter_map(|(_, recs)| recs.last())
+        .filter(|rec| !rec.retracted)
+        .collect();
+    result
+}
+
 /// Attempts to unify a new record and a clause with existing
 /// bindings.  If bound fields in the clause match the record, then
 /// any fields in the record which match an unbound clause will be
@@ -213,7 +605,7 @@ fn unify(env: &Binding, idents: &IdentMap, clause: &Clause, record: &Record) ->
         Term::Unbound(ref var) => {
             match env.get(var) {
                 Some(e) => {
-                    if *e != Value::Entity(record.entity) {
+                    if idents.resolve(e) != Some(record.entity) {
                         return None;
                     }
                 }
@@ -239,13 +631,18 @@ fn unify(env: &Binding, idents: &IdentMap, clause: &Clause, record: &Record) ->
         }
         Term::Unbound(ref var) => {
             match env.get(var) {
-                Some(e) => {
-                    if *e != Value::Entity(record.attribute) {
+                Some(a) => {
+                    if idents.resolve(a) != Some(record.attribute) {
                         return None;
                     }
                 }
                 _ => {
-                    new_env.insert(var.clone(), Value::Entity(record.attribute));
+                    // Bind attributes by ident, so the results are readable.
+                    let attr = match idents.get_ident(record.attribute) {
+                        Some(ident) => Value::Ident(ident),
+                        None => Value::Entity(record.attribute),
+                    };
+                    new_env.insert(var.clone(), attr);
                 }
             }
         }
@@ -253,14 +650,14 @@ fn unify(env: &Binding, idents: &IdentMap, clause: &Clause, record: &Record) ->
 
     match clause.value {
         Term::Bound(ref v) => {
-            if *v != record.value {
+            if !idents.same_value(v, &record.value) {
                 return None;
             }
         }
         Term::Unbound(ref var) => {
             match env.get(var) {
                 Some(e) => {
-                    if *e != record.value {
+                    if !idents.same_value(e, &record.value) {
                         return None;
                     }
                 }
@@ -271,6 +668,49 @@ fn unify(env: &Binding, idents: &IdentMap, clause: &Clause, record: &Record) ->
         }
     }
 
+    match clause.tx {
+        Some(Term::Bound(ref tx)) => {
+            if *tx != record.tx {
+                return None;
+            }
+        }
+        Some(Term::Unbound(ref var)) => {
+            match env.get(var) {
+                Some(tx) => {
+                    if *tx != Value::Entity(record.tx) {
+                        return None;
+                    }
+                }
+                _ => {
+                    new_env.insert(var.clone(), Value::Entity(record.tx));
+                }
+            }
+        }
+        None => {}
+    }
+
+    let added = Value::Boolean(!record.retracted);
+    match clause.added {
+        Some(Term::Bound(ref v)) => {
+            if *v != added {
+                return None;
+            }
+        }
+        Some(Term::Unbound(ref var)) => {
+            match env.get(var) {
+                Some(v) => {
+                    if *v != added {
+                        return None;
+                    }
+                }
+                _ => {
+                    new_env.insert(var.clone(), added);
+                }
+            }
+        }
+        None => {}
+    }
+
     Some(new_env)
 }
 
@@ -283,20 +723,24 @@ pub struct DbContents {
     pub eav: String,
     pub ave: String,
     pub aev: String,
+    #[serde(default)]
+    pub fulltext: Option<String>,
 }
 
 pub fn store_from_uri(uri: &str) -> Result<Arc<KVStore>> {
     match &uri.split("//").collect::<Vec<_>>()[..] {
         &["logos:mem:", _] => Ok(Arc::new(HeapStore::new::<Record>()) as Arc<KVStore>),
+        #[cfg(feature = "stores")]
         &["logos:sqlite:", path] => {
             let sqlite_store = SqliteStore::new(path)?;
             Ok(Arc::new(sqlite_store) as Arc<KVStore>)
         }
+        #[cfg(feature = "stores")]
         &["logos:cass:", url] => {
             let cass_store = CassandraStore::new(url)?;
             Ok(Arc::new(cass_store) as Arc<KVStore>)
         }
-        _ => Err("Invalid uri".into()),
+        _ => Err(Error::Validation(format!("invalid uri {}", uri))),
     }
 }
 
@@ -349,9 +793,7 @@ mod tests {
             Fact::new(Entity(1), "parent", Entity(0)),
         ];
 
-        parse_tx("{db:ident name} {db:ident parent} {db:ident Hello}")
-            .map_err(|e| e.into())
-            .and_then(|tx| conn.transact(tx))
+        conn.transact(parse_tx("{db:ident name} {db:ident parent} {db:ident Hello}").unwrap())
             .unwrap();
 
         conn.transact(Tx {
@@ -365,6 +807,15 @@ mod tests {
         conn
     }
 
+    /// Declares `attr` a ref, so that integers given as its values are
+    /// stored as entities.
+    fn declare_ref(conn: &Conn, attr: &str) {
+        let attr = conn.db().unwrap().idents.get_entity(attr.to_string()).unwrap();
+        conn.transact(parse_tx("{db:ident db:valueType}").unwrap()).unwrap();
+        conn.transact(parse_tx(&*format!("add ({} db:valueType ref)", attr.0)).unwrap())
+            .unwrap();
+    }
+
     fn test_db() -> Db {
         test_conn().db().unwrap()
     }
@@ -393,19 +844,66 @@ mod tests {
 
     }
 
-    // // It's inconvenient to test this because we don't have a ref to the db in
-    // // the current setup, and we don't know the entity id of `name` offhand.
-    // #[test]
-    // fn test_query_unknown_attribute() {
-    //     // find ?a where (1 ?a "John")
-    //     expect_query_result(&parse_query("find ?a where (1 ?a \"John\")").unwrap(),
-    //                         QueryResult(vec![Var::new("a")],
-    //                                     vec![
-    //         iter::once((Var::new("a"),
-    //                     Value::String("name".into())))
-    //                 .collect(),
-    //     ]));
-    // }
+    #[test]
+    fn test_query_unknown_attribute() {
+        // find ?a where (1 ?a "John")
+        expect_query_result(&parse_query("find ?a where (1 ?a \"John\")").unwrap(),
+                            QueryResult(vec![Var::new("a")],
+                                        vec![
+            iter::once((Var::new("a"),
+                        Value::Ident("name".into())))
+                    .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_query_bound_attribute() {
+        // find ?n where (1 ?a "John") (0 ?a ?n)
+        expect_query_result(&parse_query("find ?n where (1 ?a \"John\") (0 ?a ?n)").unwrap(),
+                            QueryResult(vec![Var::new("n")],
+                                        vec![
+            iter::once((Var::new("n"),
+                        Value::String("Bob".into())))
+                    .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_query_attribute_entity() {
+        // find ?e where (1 ?a "John") (?a db:ident ?i) (?e db:ident ?i)
+        let db = test_db();
+        let name = db.idents.get_entity("name".to_string()).unwrap();
+        let result = db.query(&parse_query("find ?a ?e where (1 ?a \"John\") (?e db:ident ?i) (?a db:ident ?i)")
+                                   .unwrap())
+            .unwrap();
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("a"), Var::new("e")],
+                               vec![
+            vec![
+                (Var::new("a"), Value::Ident("name".into())),
+                (Var::new("e"), Value::Entity(name)),
+            ]
+                    .into_iter()
+                    .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_query_ident_value() {
+        // An ident in value position matches references to its entity.
+        let conn = test_conn();
+        let name = conn.db().unwrap().idents.get_entity("name".to_string()).unwrap();
+        conn.transact(parse_tx(&*format!("add (2 parent {})", name.0)).unwrap()).unwrap();
+        let db = conn.db().unwrap();
+        let referrers = |q: &str| {
+            let result = db.query(&parse_query(q).unwrap()).unwrap();
+            result.1.iter().map(|b| b[&Var::new("e")].clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(referrers("find ?e where (?e parent name)"), vec![Value::Entity(Entity(2))]);
+        assert_eq!(referrers("find ?e where (1 ?a \"John\") (?e parent ?a)"),
+                   vec![Value::Entity(Entity(2))]);
+    }
 
     #[test]
     fn test_query_multiple_results() {
@@ -453,10 +951,542 @@ mod tests {
     }
 
     #[test]
-    fn test_type_mismatch() {
+    fn test_query_builder() {
+        let q = Query::find(&["?c"])
+            .where_(var("?a"), "name", "Bob")
+            .where_(var("?b"), "name", var("?c"))
+            .where_(var("?b"), "parent", var("?a"))
+            .build()
+            .unwrap();
+        assert_eq!(q,
+                   parse_query("find ?c where (?a name \"Bob\") (?b name ?c) (?b parent ?a)")
+                       .unwrap());
+
+        let by_entity = Query::find(&["?n"]).where_(Entity(1), "name", var("?n")).build().unwrap();
+        assert_eq!(test_db().query(&by_entity).unwrap().1[0][&var("n")],
+                   Value::String("John".into()));
+
+        match Query::find(&["?e", "?x"]).where_(var("?e"), "name", "Bob").build() {
+            Err(Error::Validation(ref msg)) if msg.contains("?x") => {}
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+        assert!(Query::find(&[]).where_(var("?e"), "name", "Bob").build().is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        let db = test_db();
+        let plan = db.explain(&parse_query("find ?c where (?a name \"Bob\") (?b parent ?a) (?b name ?c) \
+                                            (?x ?y \"World\") (?e name ?n)")
+                                       .unwrap())
+            .unwrap();
+        let steps = plan.steps
+            .iter()
+            .map(|step| (step.access, step.estimated_rows))
+            .collect::<Vec<_>>();
+        assert_eq!(steps,
+                   vec![(Access::Avet, 1),
+                        (Access::Avet, 1),
+                        (Access::Eavt, 1),
+                        (Access::FullScan, 1),
+                        (Access::Aevt, 2)]);
+        assert_eq!(plan.steps[1].clause.to_string(), "(?b parent ?a)");
+
+        // Estimates only read so much of each index.
+        let conn = test_conn();
+        conn.transact(Tx {
+                          items: (10..1110)
+                              .map(|e| TxItem::Addition(Fact::new(Entity(e), "name", "Ann")))
+                              .collect(),
+                      })
+            .unwrap();
+        let plan = conn.db()
+            .unwrap()
+            .explain(&parse_query("find ?e where (?e name \"Ann\")").unwrap())
+            .unwrap();
+        assert_eq!(plan.steps[0].estimated_rows, 1000);
+    }
+
+    #[test]
+    fn test_profile() {
         let db = test_db();
+        let q = parse_query("find ?c where (?a name \"Bob\") (?b parent ?a) (?b name ?c)").unwrap();
+        let profile = db.profile(&q).unwrap();
+
+        assert_eq!(profile.result, db.query(&q).unwrap());
+        for step in &profile.steps {
+            assert_eq!(step.stats.rows, 1);
+            assert!(step.stats.cache.nodes() > 0);
+        }
+        assert_eq!(profile.steps[2].access, Access::Eavt);
+        assert!(profile.rules.is_none());
+
+        let unbound = parse_query("find ?z where (?a name \"Bob\")").unwrap();
+        match db.profile(&unbound) {
+            Err(Error::Validation(_)) => {}
+            other => panic!("expected a validation error, got {:?}", other.map(|p| p.steps.len())),
+        }
+    }
+
+    #[test]
+    fn test_prepared_query() {
+        let conn = test_conn();
+        let q = parse_query("find ?c in ?name where (?a name ?name) (?b parent ?a) (?b name ?c)")
+            .unwrap();
+        let prepared = conn.db().unwrap().prepare(&q).unwrap();
+        let children = |db: &Db, name: &str| {
+            let result = prepared.execute(db, &[("?name", name.into())]).unwrap();
+            result.1.iter().map(|b| b[&Var::new("c")].clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(children(&conn.db().unwrap(), "Bob"), vec![Value::String("John".into())]);
+        assert_eq!(children(&conn.db().unwrap(), "John"), vec![]);
+
+        // A later Db of the same database can run the same plan.
+        conn.transact(parse_tx("add (3 name \"Jane\") add (3 parent 1)").unwrap()).unwrap();
+        assert_eq!(children(&conn.db().unwrap(), "John"), vec![Value::String("Jane".into())]);
+
+        let db = conn.db().unwrap();
+        assert!(prepared.execute(&db, &[]).is_err());
+        assert!(prepared.execute(&db, &[("?name", "Bob".into()), ("?x", "Bob".into())]).is_err());
+        assert_eq!(db.explain(&q).unwrap().steps[0].access, Access::Avet);
+
+        // A database where `name` is a different entity has a different schema.
+        let other = Conn::new(Arc::new(HeapStore::new::<Record>())).unwrap();
+        other.transact(parse_tx("{db:ident parent} {db:ident name}").unwrap()).unwrap();
+        match prepared.execute(&other.db().unwrap(), &[("?name", "Bob".into())]) {
+            Err(Error::Schema(_)) => {}
+            other => panic!("expected a schema error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let cache = Arc::new(ResultCache::new(2));
+        let conn = test_conn().with_result_cache(cache.clone());
+        let q = parse_query("find ?c in ?name where (?a name ?name) (?b parent ?a) (?b name ?c)")
+            .unwrap();
+        let prepared = conn.db().unwrap().prepare(&q).unwrap();
+        let run = |name: &str| {
+            prepared.execute(&conn.db().unwrap(), &[("?name", name.into())]).unwrap()
+        };
+
+        let first = run("Bob");
+        assert_eq!(run("Bob"), first);
+        assert_eq!(cache.metrics(),
+                   CacheMetrics {
+                       hits: 1,
+                       misses: 1,
+                       evictions: 0,
+                       entries: 1,
+                   });
+
+        // Other inputs and other bases are cached separately.
+        run("John");
+        let tx = match conn.transact(parse_tx("add (3 name \"Jane\") add (3 parent 0)").unwrap()) {
+            Ok(TxReport::Success { tx, .. }) => tx,
+            other => panic!("transaction failed: {:?}", other),
+        };
+        assert_eq!(run("Bob").1.len(), 2);
+        assert_eq!(cache.metrics().misses, 3);
+        assert_eq!(cache.metrics().evictions, 1);
+        assert_eq!(cache.metrics().entries, 2);
+
+        // Views of the same indexes are different bases too.
+        let db = conn.db().unwrap();
+        let q = parse_query("find ?n where (?e name ?n)").unwrap();
+        assert_eq!(db.query(&q).unwrap().1.len(), 3);
+        assert_eq!(db.since(tx).unwrap().query(&q).unwrap().1.len(), 0);
+
+        // The least recently used result is the one evicted.
+        let cache = Arc::new(ResultCache::new(2));
+        let mut db = conn.db().unwrap();
+        db.cache = Some(cache.clone());
+        let run = |name: &str| prepared.execute(&db, &[("?name", name.into())]).unwrap();
+        run("Bob");
+        run("John");
+        run("Bob");
+        run("Jane");
+        assert_eq!(cache.metrics().evictions, 1);
+        run("Bob");
+        assert_eq!(cache.metrics().hits, 2);
+        run("John");
+        assert_eq!(cache.metrics().misses, 4);
+    }
+
+    #[test]
+    fn test_query_limits() {
+        use {Abort, CancelToken, QueryOptions};
+        use std::time::Duration;
+
+        let db = test_db();
+        let q = parse_query("find ?a ?b where (?a name ?x) (?b name ?y)").unwrap();
+        let aborted = |options: QueryOptions| match db.query_with_options(&q, &options) {
+            Err(Error::Aborted(abort)) => abort,
+            other => panic!("expected the query to be aborted, got {:?}", other),
+        };
+
+        assert_eq!(aborted(QueryOptions::new().max_bindings(3)), Abort::Bindings(3));
+        assert_eq!(aborted(QueryOptions::new().max_nodes(0)), Abort::Nodes(0));
+        assert_eq!(aborted(QueryOptions::new().timeout(Duration::from_secs(0))),
+                   Abort::Deadline);
+
+        let token = CancelToken::new();
+        token.clone().cancel();
+        assert_eq!(aborted(QueryOptions::new().cancel_on(token.clone())), Abort::Cancelled);
+        // Profiling runs the query, so it's cancelled too.
+        match db.with_options(&QueryOptions::new().cancel_on(token)).profile(&q) {
+            Err(Error::Aborted(Abort::Cancelled)) => {}
+            other => panic!("expected the profile to be cancelled, got {:?}", other),
+        }
+
+        let options = QueryOptions::new()
+            .max_bindings(100)
+            .max_nodes(1000)
+            .timeout(Duration::from_secs(60))
+            .cancel_on(CancelToken::new());
+        assert_eq!(db.query_with_options(&q, &options).unwrap(), db.query(&q).unwrap());
+    }
+
+    #[test]
+    fn test_range_predicates() {
+        let conn = test_conn();
+        conn.transact(parse_tx("{db:ident age}").unwrap()).unwrap();
+        conn.transact(parse_tx("add (0 age 45) add (1 age 30) add (2 age 31.5) add (3 age -4) \
+                                add (4 age \"old\")")
+                              .unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+        let ages = |q: &str| {
+            let result = db.query(&parse_query(q).unwrap()).unwrap();
+            let mut ages = result.1.iter().map(|b| b[&Var::new("a")].clone()).collect::<Vec<_>>();
+            ages.sort_by(Value::index_cmp);
+            ages
+        };
+
+        assert_eq!(ages("find ?a where (?e age ?a) [(> ?a 30)]"),
+                   vec![Value::Float(Float(31.5)), Value::Integer(45)]);
+        assert_eq!(ages("find ?a where (?e age ?a) [(<= ?a 30)]"),
+                   vec![Value::Integer(-4), Value::Integer(30)]);
+        assert_eq!(ages("find ?a where (?e age ?a) [(< -10 ?a)] [(< ?a 31.5)]"),
+                   vec![Value::Integer(-4), Value::Integer(30)]);
+        assert_eq!(ages("find ?a where (?e age ?a) [(> ?a \"a\")]"),
+                   vec![Value::String("old".into())]);
+        assert_eq!(ages("find ?a where (1 age ?min) (?e age ?a) [(> ?a ?min)] [(!= ?a \"old\")]"),
+                   vec![Value::Float(Float(31.5)), Value::Integer(45)]);
+
+        // Only the part of the index within the bounds is read.
+        let q = parse_query("find ?a where (?e age ?a) [(>= ?a 30)] [(< ?a 40)]").unwrap();
+        assert_eq!(db.explain(&q).unwrap().steps[0].access, Access::AvetRange);
+        let plan = ClausePlan {
+            attribute: None,
+            access: Access::AvetRange,
+            range: q.predicates.clone(),
+        };
+        let scanned = db.records_matching(&q.clauses[0], &HashMap::new(), Some(&plan))
+            .unwrap()
+            .into_iter()
+            .map(|rec| rec.value)
+            .collect::<Vec<_>>();
+        assert_eq!(scanned, vec![Value::Integer(30), Value::Float(Float(31.5))]);
+    }
+
+    #[test]
+    fn test_functions() {
+        let db = test_conn().db().unwrap();
+        let rows = |q: &str, vars: &[&str]| {
+            let result = db.query(&parse_query(q).unwrap()).unwrap();
+            let mut rows = result.1
+                .iter()
+                .map(|b| vars.iter().map(|v| b[&Var::new(*v)].to_string()).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            rows.sort();
+            rows
+        };
+
+        assert_eq!(rows("find ?n ?p where (?e name ?n) [(get-else ?e parent \"none\") ?p]",
+                        &["n", "p"]),
+                   vec![vec!["\"Bob\"", "\"none\""], vec!["\"John\"", "0"]]);
+        assert_eq!(rows("find ?n where (?e name ?n) [(missing? ?e parent)]", &["n"]),
+                   vec![vec!["\"Bob\""]]);
+        assert_eq!(rows("find ?a ?b where (?e name ?n) [(tuple ?e ?n) ?t] [(untuple ?t) [?a ?b]]",
+                        &["a", "b"]),
+                   vec![vec!["0", "\"Bob\""], vec!["1", "\"John\""]]);
+        assert_eq!(rows("find ?m where (?e name ?n) [(identity ?n) ?m] [(!= ?m \"Bob\")]",
+                        &["m"]),
+                   vec![vec!["\"John\""]]);
+        assert_eq!(rows("find ?n ?x where [(ground [[0 \"x\"] [1 \"y\"]]) [[?e ?x]]] (?e name ?n)",
+                        &["n", "x"]),
+                   vec![vec!["\"Bob\"", "\"x\""], vec!["\"John\"", "\"y\""]]);
+
+        // Calls that skipped validation still have their arguments
+        // counted.
+        let call = Call::new(Function::GetElse, vec![Term::Bound(Value::Entity(Entity(0)))]);
+        match db.call(&call, vec![HashMap::new()]) {
+            Err(Error::Validation(msg)) => {
+                assert_eq!(msg, "get-else takes 3 arguments, not 1");
+            }
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        // Calls run as soon as what they read is bound, so the clause
+        // can look up the entities ground binds.
+        let q = parse_query("find ?n where [(ground [0 1 2]) [?e ...]] (?e name ?n)").unwrap();
+        assert_eq!(db.explain(&q).unwrap().steps[0].access, Access::Eavt);
+        assert_eq!(db.query(&q).unwrap().1.len(), 2);
+
+        let q = parse_query("find ?x where (?e name ?n) [(get-else ?e age 0) ?x]").unwrap();
+        assert!(db.query(&q).is_err());
+        let q = parse_query("find ?x where (?e name ?n) [(identity ?y) ?x] [(identity ?x) ?y]")
+            .unwrap();
+        assert!(db.query(&q).is_err());
+        let q = parse_query("find ?x where (?e name ?n) [(get-else ?e parent) ?x]").unwrap();
+        assert!(db.query(&q).is_err());
+    }
+
+    #[test]
+    fn test_graph_functions() {
+        let conn = test_conn();
+        // A cycle 10 -> 11 -> 12 -> 13 -> 10, with 14 -> 12 leading into it.
+        conn.transact(parse_tx("add (10 parent 11) add (11 parent 12) add (12 parent 13) \
+                                add (13 parent 10) add (14 parent 12)")
+                              .unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+        let entities = |q: &str| {
+            let result = db.query(&parse_query(q).unwrap()).unwrap();
+            let mut entities = result.1
+                .iter()
+                .map(|b| b[&Var::new("e")].clone())
+                .collect::<Vec<_>>();
+            entities.sort();
+            entities
+        };
+        let ids = |ids: &[u64]| ids.iter().map(|&e| Value::Entity(Entity(e))).collect::<Vec<_>>();
+
+        assert_eq!(entities("find ?e where [(reachable 10 parent) [?e ...]]"),
+                   ids(&[10, 11, 12, 13]));
+        assert_eq!(entities("find ?e where [(reachable 10 parent ?e)]"),
+                   ids(&[10, 11, 12, 13]));
+        assert_eq!(entities("find ?e where [(reachable 10 parent 2 ?e)]"), ids(&[11, 12]));
+        assert_eq!(entities("find ?e where [(reachable 12 _parent 1) [?e ...]]"), ids(&[11, 14]));
+        assert_eq!(entities("find ?e where [(ground [10 14 0]) [?e ...]] \
+                             [(reachable ?e parent ?to)] [(= ?to 13)]"),
+                   ids(&[10, 14]));
+
+        assert_eq!(entities("find ?e where [(shortest-path 14 10 parent) ?e]"),
+                   vec![Value::List(ids(&[14, 12, 13, 10]))]);
+        assert_eq!(entities("find ?e where [(shortest-path 10 10 parent) ?e]"),
+                   vec![Value::List(ids(&[10]))]);
+        assert_eq!(entities("find ?e where [(shortest-path 10 14 parent) ?e]"), vec![]);
+        assert_eq!(entities("find ?e where [(shortest-path 14 10 parent 2 ?e)]"), vec![]);
+        assert_eq!(entities("find ?e where [(shortest-path 14 10 parent 3 ?e)]"),
+                   vec![Value::List(ids(&[14, 12, 13, 10]))]);
+        assert_eq!(entities("find ?e where [(shortest-path 10 14 _parent) ?e]"),
+                   vec![Value::List(ids(&[10, 13, 12, 14]))]);
+    }
+
+    #[test]
+    fn test_fulltext() {
+        let conn = test_conn();
+        conn.transact(parse_tx("{db:ident db:fulltext}").unwrap()).unwrap();
+        conn.transact(parse_tx("{db:ident bio db:fulltext true}").unwrap()).unwrap();
+        conn.transact(parse_tx("add (0 bio \"Bob Smith, builder\") add (1 bio \"John Smith\")")
+                              .unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+
+        let q = parse_query("find ?e ?score where [(fulltext $ bio \"bob smith\") \
+                                                   [[?e ?v ?score]]]")
+                .unwrap();
+        let mut rows = db.query(&q)
+            .unwrap()
+            .1
+            .iter()
+            .map(|b| (b[&Var::new("e")].clone(), b[&Var::new("score")].clone()))
+            .collect::<Vec<_>>();
+        rows.sort();
+        assert_eq!(rows,
+                   vec![(Value::Entity(Entity(0)), Value::Float(Float(1.0))),
+                        (Value::Entity(Entity(1)), Value::Float(Float(0.5)))]);
+
+        // Only db:fulltext attributes can be searched.
+        let q = parse_query("find ?e where [(fulltext name \"bob\") [[?e ?v ?s]]]").unwrap();
+        assert!(db.query(&q).is_err());
+        assert!(parse_query("find ?e where [(fulltext $old bio \"bob\") [[?e ?v ?s]]]").is_err());
+    }
+
+    #[test]
+    fn test_multiple_sources() {
+        let conn = test_conn();
+        let before = conn.db().unwrap();
+        conn.transact(parse_tx("retract (0 name \"Bob\") add (0 name \"Robert\")").unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+        let values = |result: QueryResult, var: &str| {
+            result.1.iter().map(|b| b[&Var::new(var)].clone()).collect::<Vec<_>>()
+        };
+
+        // The same database at two points in time.
+        let q = parse_query("find ?old in $before where $before (?e name ?old) (?e name ?new) \
+                             [(!= ?old ?new)]")
+                .unwrap();
+        let result = db.query_with_sources(&q, &[("$before", Source::Db(&before))]).unwrap();
+        assert_eq!(values(result, "old"), vec![Value::String("Bob".into())]);
+
+        // A separate store, where the attributes are different entities.
+        let other = Conn::new(Arc::new(HeapStore::new::<Record>())).unwrap();
+        other.transact(parse_tx("{db:ident nickname} {db:ident name}").unwrap()).unwrap();
+        other.transact(parse_tx("add (7 name \"Robert\") add (7 nickname \"Bobby\")").unwrap())
+            .unwrap();
+        let q = parse_query("find ?nick in $other where (?e parent ?p) (?p name ?n) \
+                             $other (?o name ?n) $other (?o nickname ?nick)")
+                .unwrap();
+        let result = db.query_with_sources(&q, &[("$other", Source::Db(&other.db().unwrap()))])
+            .unwrap();
+        assert_eq!(values(result, "nick"), vec![Value::String("Bobby".into())]);
+
+        // An in-memory relation, matched by position.
+        let ages = vec![vec![Value::String("John".into()), Value::Integer(30)],
+                        vec![Value::String("Jim".into()), Value::Integer(50)],
+                        vec![Value::String("John".into())]];
+        let q = parse_query("find ?e ?a in $ages where (?e name ?n) $ages [?n ?a]").unwrap();
+        let prepared = db.prepare(&q).unwrap();
+        let result = prepared.execute_with_sources(&db, &[("$ages", Source::Relation(&ages))], &[])
+            .unwrap();
+        assert_eq!(values(result.clone(), "e"), vec![Value::Entity(Entity(1))]);
+        assert_eq!(values(result, "a"), vec![Value::Integer(30)]);
+        let q = parse_query("find ?n in $ages where $ages [?n]").unwrap();
+        let result = db.query_with_sources(&q, &[("$ages", Source::Relation(&ages))]).unwrap();
+        assert_eq!(values(result, "n"), vec![Value::String("John".into())]);
+
+        assert!(prepared.execute(&db, &[]).is_err());
+        assert!(prepared
+                    .execute_with_sources(&db, &[("$x", Source::Relation(&ages))], &[])
+                    .is_err());
+        assert!(prepared
+                    .execute_with_sources(&db, &[("$ages", Source::Db(&db))], &[])
+                    .is_err());
+        let q = parse_query("find ?n in $ages where $ages (?e name ?n)").unwrap();
+        assert!(db.query_with_sources(&q, &[("$ages", Source::Relation(&ages))]).is_err());
+        assert!(db.query(&parse_query("find ?n where $old (?e name ?n)").unwrap()).is_err());
+    }
+
+    /// `test_conn` with `name` declared a string and `parent` a ref.
+    fn typed_db() -> Db {
+        let conn = test_conn();
+        let db = conn.db().unwrap();
+        let name = db.idents.get_entity("name".to_string()).unwrap();
+        let parent = db.idents.get_entity("parent".to_string()).unwrap();
+        conn.transact(parse_tx("{db:ident db:valueType}").unwrap()).unwrap();
+        let schema = format!("add ({} db:valueType string) add ({} db:valueType ref)",
+                             name.0,
+                             parent.0);
+        conn.transact(parse_tx(&*schema).unwrap()).unwrap();
+        conn.db().unwrap()
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let db = typed_db();
         let q = &parse_query("find ?e ?n where (?e name ?n) (?n name \"hi\")").unwrap();
-        assert_equal(db.query(&q), Err("type mismatch".to_string()))
+        match db.query(&q) {
+            Err(Error::Validation(msg)) => {
+                assert_eq!(msg,
+                           "?n is a string (from `name`) but used in entity position of clause 2")
+            }
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_schema_check() {
+        let db = typed_db();
+        let error = |q: &str| match db.query(&parse_query(q).unwrap()) {
+            Err(Error::Validation(msg)) |
+            Err(Error::Schema(msg)) => msg,
+            other => panic!("expected an error, got {:?}", other),
+        };
+
+        // The check happens before anything is read, so the entity
+        // position is caught even when it comes first.
+        assert_eq!(error("find ?n where (?n parent ?p) (?e name ?n)"),
+                   "?n is a string (from `name`) but used in entity position of clause 1");
+        assert_eq!(error("find ?e where (?e name ?n) (?p parent ?n)"),
+                   "?n is a string (from `name`) but a ref (from `parent`) in clause 2");
+        assert_eq!(error("find ?e where (?e name 5)"),
+                   "5 is not a string (the type of `name`) in clause 1");
+        assert_eq!(error("find ?e where (?e name ?n) (?e nmae ?m)"),
+                   "unknown attribute `nmae` in clause 2");
+
+        // Attributes without a declared type are only checked as the
+        // query runs.
+        assert_eq!(error("find ?e where (?e Hello ?v) (?v name ?n)"),
+                   "?v is \"World\", which is not an entity");
+        assert!(db.query(&parse_query("find ?c where (?c parent ?p) (?p name \"Bob\")").unwrap())
+                    .is_ok());
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let conn = test_conn();
+        declare_ref(&conn, "parent");
+        conn.transact(parse_tx("{db:ident age}").unwrap()).unwrap();
+        conn.transact(parse_tx("add (2 parent 1) add (2 age 30)").unwrap()).unwrap();
+        let db = conn.db().unwrap();
+        let values = |q: &str| {
+            let result = db.query(&parse_query(q).unwrap()).unwrap();
+            result.1.iter().map(|b| b[&Var::new("v")].clone()).collect::<Vec<_>>()
+        };
+
+        // Numbers are only entities as the values of refs.
+        assert_eq!(values("find ?v where (2 parent ?v)"), vec![Value::Entity(Entity(1))]);
+        assert_eq!(values("find ?v where (2 age ?v)"), vec![Value::Integer(30)]);
+        assert_eq!(values("find ?v where (?v parent 1)"), vec![Value::Entity(Entity(2))]);
+        assert_eq!(values("find ?v where [(ground 2) ?e] (?e age ?v)"),
+                   vec![Value::Integer(30)]);
+    }
+
+    #[test]
+    fn test_storage_error() {
+        let conn = test_conn();
+        let mut contents = conn.store.get_contents().unwrap();
+        contents.eav = "missing".to_string();
+        let db = Db::new(contents, conn.store.clone());
+
+        match db.query(&parse_query("find ?n where (0 name ?n)").unwrap()) {
+            Err(Error::Storage(_)) => {}
+            other => panic!("expected a storage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_non_ident_db_ident() {
+        let report = test_conn()
+            .transact(parse_tx("add (0 db:ident \"Bob\")").unwrap())
+            .unwrap();
+        match report {
+            TxReport::Failure(Error::Validation(_)) => {}
+            other => panic!("expected a validation failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_retract_ident() {
+        let conn = test_conn();
+        let idents = conn.db().unwrap().idents;
+        let hello = idents.get_entity("Hello".to_string()).unwrap();
+        conn.transact(parse_tx(&*format!("retract ({} db:ident Hello)", hello.0)).unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+
+        assert_eq!(db.idents.get_entity("Hello".to_string()), None);
+        assert_eq!(db.idents.get_entity("name".to_string()),
+                   idents.get_entity("name".to_string()));
+        match db.query(&parse_query("find ?v where (?e Hello ?v)").unwrap()) {
+            Err(Error::Schema(_)) => {}
+            other => panic!("expected a schema error, got {:?}", other),
+        }
     }
 
     #[test]
@@ -472,6 +1502,369 @@ mod tests {
         assert_eq!(result,
                    QueryResult(vec![Var::new("a"), Var::new("b")], vec![]));
     }
+
+    #[test]
+    fn test_reassertion() {
+        let conn = test_conn();
+        conn.transact(parse_tx("retract (1 parent 0)").unwrap())
+            .unwrap();
+        declare_ref(&conn, "parent");
+        conn.transact(parse_tx("add (1 parent 0)").unwrap())
+            .unwrap();
+        let result = conn.db()
+            .unwrap()
+            .query(&parse_query("find ?a ?b where (?a parent ?b)").unwrap())
+            .unwrap();
+
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("a"), Var::new("b")],
+                               vec![
+            vec![
+                (Var::new("a"), Value::Entity(Entity(1))),
+                (Var::new("b"), Value::Entity(Entity(0))),
+            ]
+                    .into_iter()
+                    .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_redundant_assertion() {
+        let conn = test_conn();
+        conn.transact(parse_tx("add (0 name \"Bob\")").unwrap())
+            .unwrap();
+        let result = conn.db()
+            .unwrap()
+            .query(&parse_query("find ?a where (?a name \"Bob\")").unwrap())
+            .unwrap();
+
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("a")],
+                               vec![
+            iter::once((Var::new("a"), Value::Entity(Entity(0))))
+                .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_retractions_across_joins() {
+        let conn = test_conn();
+        conn.transact(parse_tx("retract (1 name \"John\")").unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+
+        let names = db.query(&parse_query("find ?c where (?a name \"Bob\") (?b parent ?a) (?b name ?c)")
+                                  .unwrap())
+            .unwrap();
+        assert_eq!(names, QueryResult(vec![Var::new("c")], vec![]));
+
+        let children = db.query(&parse_query("find ?b where (?a name \"Bob\") (?b parent ?a)").unwrap())
+            .unwrap();
+        assert_eq!(children,
+                   QueryResult(vec![Var::new("b")],
+                               vec![
+            iter::once((Var::new("b"), Value::Entity(Entity(1))))
+                .collect(),
+        ]));
+    }
+    #[test]
+    fn test_tx_instants() {
+        let db = test_db();
+        let result = db.query(&parse_query("find ?n ?t where (?e name ?n ?tx) (?tx db:txInstant ?t)")
+                                  .unwrap())
+            .unwrap();
+
+        assert_eq!(result.1.len(), 2);
+        for row in result.1 {
+            match row[&Var::new("t")] {
+                Value::Timestamp(_) => {}
+                ref v => panic!("expected a timestamp, got {}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_on_tx() {
+        let conn = test_conn();
+        conn.transact(parse_tx("{db:ident author}").unwrap())
+            .unwrap();
+        let tx = match conn.transact(parse_tx("add (1 name \"Johnny\")").unwrap())
+                  .unwrap() {
+            TxReport::Success { tx, .. } => tx,
+            TxReport::Failure(e) => panic!("{}", e),
+        };
+        conn.transact(Tx { items: vec![TxItem::Addition(Fact::new(tx, "author", "X"))] })
+            .unwrap();
+
+        let result = conn.db()
+            .unwrap()
+            .query(&parse_query("find ?n where (?e name ?n ?tx) (?tx author \"X\")").unwrap())
+            .unwrap();
+
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("n")],
+                               vec![
+            iter::once((Var::new("n"), Value::String("Johnny".into())))
+                .collect(),
+        ]));
+
+        // A bound ?tx restricts the clause to facts from that transaction.
+        let result = conn.db()
+            .unwrap()
+            .query(&parse_query("find ?n ?added where (?tx author \"X\") (?e name ?n ?tx ?added)")
+                        .unwrap())
+            .unwrap();
+
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("n"), Var::new("added")],
+                               vec![
+            vec![
+                (Var::new("n"), Value::String("Johnny".into())),
+                (Var::new("added"), Value::Boolean(true)),
+            ]
+                    .into_iter()
+                    .collect(),
+        ]));
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    #[test]
+    fn test_pull() {
+        let db = test_db();
+        let pattern = parse_pull_pattern("[name {parent [name]} (default Hello \"none\")]").unwrap();
+
+        assert_eq!(db.pull(&pattern, Entity(1)).unwrap(),
+                   map(vec![
+                       ("name", Value::String("John".into())),
+                       ("parent", map(vec![("name", Value::String("Bob".into()))])),
+                       ("Hello", Value::String("none".into())),
+                   ]));
+    }
+
+    #[test]
+    fn test_pull_reverse_and_wildcard() {
+        let db = test_db();
+        let pattern = parse_pull_pattern("[* {_parent [name]}]").unwrap();
+
+        // Entity 0 is also the initial transaction, so it has a
+        // db:txInstant as well.
+        match db.pull_many(&pattern, &[Entity(0)]).unwrap().pop() {
+            Some(Value::Map(m)) => {
+                assert_eq!(m["db:id"], Value::Entity(Entity(0)));
+                assert_eq!(m["name"], Value::String("Bob".into()));
+                assert_eq!(m["_parent"],
+                           Value::List(vec![map(vec![("name", Value::String("John".into()))])]));
+            }
+            other => panic!("expected a map, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pull_in_find_spec() {
+        expect_query_result(&parse_query("find (pull ?b [name {parent [name]}]) \
+                                          where (?a name \"Bob\") (?b parent ?a)")
+                                 .unwrap(),
+                            QueryResult(vec![Var::new("b")],
+                                        vec![
+            iter::once((Var::new("b"),
+                        map(vec![
+                            ("name", Value::String("John".into())),
+                            ("parent", map(vec![("name", Value::String("Bob".into()))])),
+                        ])))
+                .collect(),
+        ]));
+    }
+
+    #[test]
+    fn test_integer_refs() {
+        let conn = test_conn();
+        // `parent` isn't declared a ref, so the 0 is stored as an integer.
+        conn.transact(parse_tx("add (2 parent 0)").unwrap()).unwrap();
+        let db = conn.db().unwrap();
+        let bob = map(vec![("name", Value::String("Bob".into()))]);
+
+        let pattern = parse_pull_pattern("[{parent [name]}]").unwrap();
+        assert_eq!(db.pull(&pattern, Entity(2)).unwrap(),
+                   map(vec![("parent", bob.clone())]));
+        let q = parse_query("find (pull ?p [name]) where (2 parent ?p)").unwrap();
+        assert_eq!(db.query(&q).unwrap().1[0][&Var::new("p")], bob);
+        let parents = db.entity(Entity(2)).refs("parent").unwrap();
+        assert_eq!(parents.iter().map(|p| p.id()).collect::<Vec<_>>(), vec![Entity(0)]);
+    }
+
+    #[test]
+    fn test_entity_navigation() {
+        let db = test_db();
+        let john = db.entity(Entity(1));
+
+        assert_eq!(john.get("name").unwrap(), Some(Value::String("John".into())));
+        assert_eq!(john.get("Hello").unwrap(), None);
+        assert!(john.get("nonexistent").is_err());
+
+        let parents = john.refs("parent").unwrap();
+        assert_eq!(parents.iter().map(|p| p.id()).collect::<Vec<_>>(), vec![Entity(0)]);
+        assert_eq!(parents[0].get("name").unwrap(), Some(Value::String("Bob".into())));
+
+        let children = parents[0].reverse_refs("parent").unwrap();
+        assert_eq!(children.iter().map(|c| c.id()).collect::<Vec<_>>(), vec![Entity(1)]);
+
+        // Entity 1 is also the db:ident attribute itself.
+        assert_eq!(john.keys().unwrap(), vec!["db:ident", "name", "parent"]);
+    }
+
+    #[test]
+    fn test_recursive_rules() {
+        let conn = test_conn();
+        declare_ref(&conn, "parent");
+        let program = parse_program("parent(0, 2).
+                                     ancestor(A, B) :- parent(A, B).
+                                     ancestor(A, B) :- parent(A, C), ancestor(C, B).
+                                     ancestor(1, B)?")
+                .unwrap();
+        conn.transact(program.tx).unwrap();
+
+        let result = conn.db().unwrap().query(&program.queries[0]).unwrap();
+        let mut ancestors = result.1.iter().map(|b| b[&Var::new("B")].clone()).collect::<Vec<_>>();
+        ancestors.sort();
+        assert_eq!(ancestors, vec![Value::Entity(Entity(0)), Value::Entity(Entity(2))]);
+
+        let unsafe_rule = parse_program("orphan(A) :- name(B, \"Bob\"). orphan(A)?").unwrap();
+        match conn.db().unwrap().query(&unsafe_rule.queries[0]) {
+            Err(Error::Validation(_)) => {}
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        // Only the rules the goals reach are evaluated.
+        let unreached = parse_program("orphan(A) :- name(B, \"Bob\").
+                                       named(E, N) :- name(E, N).
+                                       named(E, \"Bob\")?")
+                .unwrap();
+        let result = conn.db().unwrap().query(&unreached.queries[0]).unwrap();
+        assert_eq!(result.1.len(), 1);
+
+        // Long chains take a round per link.
+        conn.transact(parse_program("parent(2, 3). parent(3, 4). parent(4, 5).").unwrap().tx)
+            .unwrap();
+        let result = conn.db().unwrap().query(&program.queries[0]).unwrap();
+        assert_eq!(result.1.len(), 5);
+    }
+
+    #[test]
+    fn test_edn_schema() {
+        let conn = test_conn();
+        conn.transact(parse_tx("[{:db/ident :nickname}]").unwrap()).unwrap();
+        conn.transact(parse_tx(r#"[{:nickname "Bobby"}]"#).unwrap()).unwrap();
+        let q = parse_query(r#"[:find ?t :where [?e :nickname "Bobby" ?tx]
+                                               [?tx :db/txInstant ?t]]"#)
+                .unwrap();
+        let result = conn.db().unwrap().query(&q).unwrap();
+        assert_eq!(result.1.len(), 1);
+        match result.1[0][&Var::new("t")] {
+            Value::Timestamp(_) => {}
+            ref other => panic!("expected a timestamp, got {}", other),
+        }
+    }
+
+    #[test]
+    fn test_speculative_tx() {
+        let conn = test_conn();
+        let db = conn.db().unwrap();
+        let query = parse_query("find ?a where (?a name \"Jane\")").unwrap();
+
+        let (db_after, report) = db.with(parse_tx("{name \"Jane\"} {name \"Jill\"}").unwrap())
+            .unwrap();
+        let new_entities = match report {
+            TxReport::Success { new_entities, .. } => new_entities,
+            TxReport::Failure(e) => panic!("{}", e),
+        };
+        assert_eq!(new_entities, vec![Entity(9), Entity(10)]);
+        assert_eq!(db_after.query(&query).unwrap().1.len(), 1);
+
+        // Neither the original Db nor the connection see the change,
+        // and none of it is written to the store.
+        assert_eq!(db.query(&query).unwrap().1.len(), 0);
+        assert_eq!(conn.db().unwrap().query(&query).unwrap().1.len(), 0);
+        assert!(db.store.get(&db_after.eav.root_ref).is_err());
+
+        // Later speculation builds on earlier speculation.
+        let (db_after, _) = db_after.with(parse_tx("{name \"Jane\"}").unwrap()).unwrap();
+        assert_eq!(db_after.query(&query).unwrap().1.len(), 2);
+
+        let (_, report) = db.with(parse_tx("add (0 nonexistent \"x\")").unwrap()).unwrap();
+        assert_eq!(report,
+                   TxReport::Failure(Error::Schema("unknown attribute nonexistent".into())));
+    }
+
+    // In `test_conn`, entities 3 through 6 are the schema transaction
+    // and its new idents, and the sample facts are added in tx 7.
+    fn retracted_conn() -> Conn {
+        let conn = test_conn();
+        conn.transact(parse_tx("retract (1 parent 0)").unwrap())
+            .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_as_of() {
+        let db = retracted_conn().db().unwrap().as_of(Entity(7)).unwrap();
+        let result = db.query(&parse_query("find ?a ?b where (?a parent ?b)").unwrap())
+            .unwrap();
+
+        assert_eq!(result.1.len(), 1);
+    }
+
+    #[test]
+    fn test_as_of_instant() {
+        let db = retracted_conn().db().unwrap();
+        let instant = |tx| {
+            let q = format!("find ?t where ({} db:txInstant ?t)", tx);
+            match db.query(&parse_query(&*q).unwrap()).unwrap().1[0][&Var::new("t")] {
+                Value::Timestamp(t) => t,
+                _ => unreachable!(),
+            }
+        };
+        let query = parse_query("find ?a ?b where (?a parent ?b)").unwrap();
+
+        assert_eq!(db.as_of(instant(7)).unwrap().query(&query).unwrap().1.len(), 1);
+        assert_eq!(db.as_of(instant(8)).unwrap().query(&query).unwrap().1.len(), 0);
+    }
+
+    #[test]
+    fn test_since() {
+        let db = retracted_conn().db().unwrap().since(Entity(7)).unwrap();
+        let result = db.query(&parse_query("find ?a where (?a name ?n)").unwrap())
+            .unwrap();
+
+        assert_eq!(result, QueryResult(vec![Var::new("a")], vec![]));
+    }
+
+    #[test]
+    fn test_history() {
+        let db = retracted_conn().db().unwrap().history();
+        let result = db.query(&parse_query("find ?tx ?op where (1 parent 0 ?tx ?op)").unwrap())
+            .unwrap();
+
+        assert_eq!(result,
+                   QueryResult(vec![Var::new("tx"), Var::new("op")],
+                               vec![
+            vec![
+                (Var::new("tx"), Value::Entity(Entity(7))),
+                (Var::new("op"), Value::Boolean(true)),
+            ]
+                    .into_iter()
+                    .collect(),
+            vec![
+                (Var::new("tx"), Value::Entity(Entity(8))),
+                (Var::new("op"), Value::Boolean(false)),
+            ]
+                    .into_iter()
+                    .collect(),
+        ]));
+    }
+
     #[bench]
     // Parse + run a query on a small db
     fn parse_bench(b: &mut Bencher) {
@@ -522,9 +1915,7 @@ mod tests {
         let conn = Conn::new(Arc::new(store)).unwrap();
         let n = 10_000;
 
-        parse_tx("{db:ident name} {db:ident Hello}")
-            .map_err(|e| e.into())
-            .and_then(|tx| conn.transact(tx))
+        conn.transact(parse_tx("{db:ident name} {db:ident Hello}").unwrap())
             .unwrap();
 
         let items = (0..n).into_iter().map(|i| {
@@ -552,7 +1943,8 @@ mod tests {
             .records_matching(&Clause::new(Term::Unbound("e".into()),
                                            Term::Bound("name".into()),
                                            Term::Bound(Value::String("Bob".into()))),
-                              &Binding::default())
+                              &Binding::default(),
+                              None)
             .unwrap();
         assert_eq!(matching.len(), 1);
         let rec = &matching[0];
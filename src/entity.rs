@@ -0,0 +1,110 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use db::Db;
+use {Entity, Error, Result, Value};
+
+/// A lazily-loaded view of a single entity, for walking the graph
+/// from Rust without writing queries. Attributes are read from the
+/// `eav` index the first time they're asked for and cached on the
+/// handle after that.
+pub struct EntityHandle<'a> {
+    db: &'a Db,
+    entity: Entity,
+    values: RefCell<HashMap<Entity, Vec<Value>>>,
+    referrers: RefCell<HashMap<Entity, Vec<Entity>>>,
+    // Set once every attribute has been loaded into `values`.
+    complete: Cell<bool>,
+}
+
+impl Db {
+    pub fn entity(&self, entity: Entity) -> EntityHandle {
+        EntityHandle::new(self, entity)
+    }
+}
+
+impl<'a> EntityHandle<'a> {
+    fn new(db: &'a Db, entity: Entity) -> EntityHandle<'a> {
+        EntityHandle {
+            db,
+            entity,
+            values: RefCell::new(HashMap::new()),
+            referrers: RefCell::new(HashMap::new()),
+            complete: Cell::new(false),
+        }
+    }
+
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    /// The value of `attr`, or the first of them if it has several.
+    pub fn get(&self, attr: &str) -> Result<Option<Value>> {
+        Ok(self.get_all(attr)?.into_iter().next())
+    }
+
+    pub fn get_all(&self, attr: &str) -> Result<Vec<Value>> {
+        let attr = self.attribute(attr)?;
+        if !self.complete.get() && !self.values.borrow().contains_key(&attr) {
+            let values = self.db.attribute_values(self.entity, attr)?;
+            self.values.borrow_mut().insert(attr, values);
+        }
+
+        Ok(self.values
+               .borrow()
+               .get(&attr)
+               .cloned()
+               .unwrap_or_default())
+    }
+
+    /// Handles for the entities that `attr` refers to.
+    pub fn refs(&self, attr: &str) -> Result<Vec<EntityHandle<'a>>> {
+        Ok(self.get_all(attr)?
+               .into_iter()
+               .filter_map(|v| self.db.idents.resolve(&v))
+               .map(|e| EntityHandle::new(self.db, e))
+               .collect())
+    }
+
+    /// Handles for the entities that refer to this one through `attr`.
+    pub fn reverse_refs(&self, attr: &str) -> Result<Vec<EntityHandle<'a>>> {
+        let attr = self.attribute(attr)?;
+        if !self.referrers.borrow().contains_key(&attr) {
+            let referrers = self.db.referrers(self.entity, attr)?;
+            self.referrers.borrow_mut().insert(attr, referrers);
+        }
+
+        Ok(self.referrers.borrow()[&attr]
+               .iter()
+               .map(|&e| EntityHandle::new(self.db, e))
+               .collect())
+    }
+
+    /// The idents of every attribute this entity has a value for.
+    pub fn keys(&self) -> Result<Vec<String>> {
+        if !self.complete.get() {
+            let mut values: HashMap<Entity, Vec<Value>> = HashMap::new();
+            for rec in self.db.entity_records(self.entity)? {
+                values.entry(rec.attribute).or_insert_with(Vec::new).push(rec.value);
+            }
+            *self.values.borrow_mut() = values;
+            self.complete.set(true);
+        }
+
+        let mut keys = self.values
+            .borrow()
+            .iter()
+            .filter(|&(_, vs)| !vs.is_empty())
+            .filter_map(|(&attr, _)| self.db.idents.get_ident(attr))
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn attribute(&self, ident: &str) -> Result<Entity> {
+        match self.db.idents.get_entity(ident.to_string()) {
+            Some(attr) => Ok(attr),
+            None => Err(Error::Schema(format!("unknown attribute {}", ident))),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::result;
+
+use rmp_serde::{encode, decode};
+#[cfg(feature = "network")]
+use zmq;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The backing store failed, or didn't have the requested key.
+    Storage(String),
+    /// A node, the db contents or a message couldn't be encoded or
+    /// decoded.
+    Serialization(String),
+    /// Query or transaction input couldn't be parsed.
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// Something that isn't in the schema was used as an attribute.
+    Schema(String),
+    /// A query or transaction is well-formed but doesn't make sense,
+    /// e.g. a string used where an entity is required.
+    Validation(String),
+    /// A transaction conflicts with the current state of the database.
+    Conflict(String),
+    /// The transactor couldn't be reached.
+    Transactor(String),
+    /// A query was stopped by one of its `QueryOptions`.
+    Aborted(Abort),
+}
+
+/// Why a query was stopped before it finished.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abort {
+    /// Its `CancelToken` was cancelled.
+    Cancelled,
+    /// It was still running at its deadline.
+    Deadline,
+    /// It had more than this many intermediate bindings.
+    Bindings(usize),
+    /// It read more than this many B-tree nodes.
+    Nodes(usize),
+}
+
+impl Display for Abort {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Abort::Cancelled => write!(f, "cancelled"),
+            Abort::Deadline => write!(f, "deadline passed"),
+            Abort::Bindings(max) => write!(f, "more than {} bindings", max),
+            Abort::Nodes(max) => write!(f, "more than {} nodes read", max),
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Storage(ref msg) => write!(f, "storage error: {}", msg),
+            Error::Serialization(ref msg) => write!(f, "serialization error: {}", msg),
+            Error::Parse {
+                line,
+                column,
+                ref message,
+            } => write!(f, "parse error at line {}, column {}: {}", line, column, message),
+            Error::Schema(ref msg) => write!(f, "schema error: {}", msg),
+            Error::Validation(ref msg) => write!(f, "invalid input: {}", msg),
+            Error::Conflict(ref msg) => write!(f, "transaction conflict: {}", msg),
+            Error::Transactor(ref msg) => write!(f, "transactor error: {}", msg),
+            Error::Aborted(ref abort) => write!(f, "query aborted: {}", abort),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Storage(_) => "storage error",
+            Error::Serialization(_) => "serialization error",
+            Error::Parse { .. } => "parse error",
+            Error::Schema(_) => "schema error",
+            Error::Validation(_) => "invalid input",
+            Error::Conflict(_) => "transaction conflict",
+            Error::Transactor(_) => "transactor error",
+            Error::Aborted(_) => "query aborted",
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Storage(err.to_string())
+    }
+}
+
+impl From<encode::Error> for Error {
+    fn from(err: encode::Error) -> Error {
+        Error::Serialization(err.to_string())
+    }
+}
+
+impl From<decode::Error> for Error {
+    fn from(err: decode::Error) -> Error {
+        Error::Serialization(err.to_string())
+    }
+}
+
+#[cfg(feature = "network")]
+impl From<zmq::Error> for Error {
+    fn from(err: zmq::Error) -> Error {
+        Error::Transactor(err.to_string())
+    }
+}
@@ -0,0 +1,383 @@
+//! `Db::explain` and `Db::profile`, for finding out why a query is
+//! slow.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use pt;
+
+use btree::{CacheStats, Iter};
+use db::{Db, take_while_ok};
+use functions::{self, Step};
+use prepared::ClausePlan;
+use query::{self, Atom, Clause, Predicate, Query, Term, Var};
+use {Binding, Entity, Error, QueryResult, Record, Result, Value};
+
+/// How the records that might match a clause are found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// A range of the EAVT index, when the entity is known.
+    Eavt,
+    /// A range of the AEVT index, when only the attribute is known.
+    Aevt,
+    /// A range of the AVET index, when the attribute and value are
+    /// known but the entity isn't.
+    Avet,
+    /// Part of the AVET index, when the attribute is known and
+    /// predicates give the value a lower or upper bound, as with
+    /// `(?e age ?a) [(> ?a 30)]`.
+    AvetRange,
+    /// Every record in the database.
+    FullScan,
+}
+
+impl Access {
+    /// The access for a clause with the given terms bound, either to
+    /// constants or by earlier clauses.
+    pub(crate) fn choose(entity: bool, attribute: bool, value: bool) -> Access {
+        match (entity, attribute, value) {
+            (true, _, _) => Access::Eavt,
+            (false, true, true) => Access::Avet,
+            (false, true, false) => Access::Aevt,
+            (false, false, _) => Access::FullScan,
+        }
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Access::Eavt => "EAVT",
+            Access::Aevt => "AEVT",
+            Access::Avet => "AVET",
+            Access::AvetRange => "AVET range",
+            Access::FullScan => "full scan",
+        })
+    }
+}
+
+/// How a query will run: its clauses in the order they're matched,
+/// followed by its goals, if it has any. Calls to functions run as
+/// soon as what they read is bound, and aren't listed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+    pub goals: Vec<Atom>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    pub clause: Clause,
+    pub access: Access,
+    /// About how many records each lookup for the clause will read:
+    /// those matching its constants, divided by the number of
+    /// distinct values of each term bound by an earlier clause. Only
+    /// the first thousand records of the range it reads are counted.
+    /// This is 0 for clauses on other sources, which aren't known
+    /// until the query is run.
+    pub estimated_rows: usize,
+}
+
+/// What running a query actually took.
+#[derive(Debug)]
+pub struct Profile {
+    pub steps: Vec<ProfileStep>,
+    /// Evaluating the query's rules and goals, if it has any.
+    pub rules: Option<StepStats>,
+    pub result: QueryResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileStep {
+    pub clause: Clause,
+    pub access: Access,
+    pub stats: StepStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepStats {
+    /// The bindings left after the step.
+    pub rows: usize,
+    /// B-tree node reads during the step. These are counted for the
+    /// whole node store, so they include reads by anything else
+    /// using the database at the same time.
+    pub cache: CacheStats,
+    pub time: Duration,
+}
+
+impl Db {
+    /// Describes how `query` will be run without running it.
+    pub fn explain(&self, query: &Query) -> Result<Plan> {
+        let mut bound = query.inputs.iter().cloned().collect::<HashSet<_>>();
+        let mut steps = vec![];
+        for step in functions::schedule(query)? {
+            let clause = match step {
+                Step::Match(clause) => clause,
+                Step::Call(call) => {
+                    bound.extend(call.bind.iter().flat_map(|form| form.vars()).cloned());
+                    continue;
+                }
+                Step::Tuples(pattern) => {
+                    bound.extend(pattern.vars().into_iter().cloned());
+                    continue;
+                }
+                Step::Goals => continue,
+            };
+            steps.push(PlanStep {
+                           clause: clause.clone(),
+                           access: access(clause, &bound, &query.predicates).0,
+                           estimated_rows: self.estimate(clause, &bound)?,
+                       });
+            bound.extend(clause.vars().into_iter().cloned());
+        }
+
+        Ok(Plan {
+               steps,
+               goals: query.goals.clone(),
+           })
+    }
+
+    /// Runs `query`, recording the rows, node reads and time of each
+    /// step.
+    pub fn profile(&self, query: &Query) -> Result<Profile> {
+        query.validate()?;
+        if let Some(input) = query.inputs.first() {
+            return Err(Error::Validation(format!("?{} is an input; only queries without inputs \
+                                                  can be profiled",
+                                                 input.name)));
+        }
+        if let Some(source) = query.sources.first() {
+            return Err(Error::Validation(format!("${} is a source; only queries without other \
+                                                  sources can be profiled",
+                                                 source)));
+        }
+
+        let mut bound = HashSet::new();
+        let mut bindings = vec![HashMap::new()];
+        let mut steps = vec![];
+        let mut rules = None;
+        for step in functions::schedule(query)? {
+            let clause = match step {
+                Step::Match(clause) => clause,
+                Step::Call(call) => {
+                    bindings = query::filter(&query.predicates, self.call(call, bindings)?);
+                    bound.extend(call.bind.iter().flat_map(|form| form.vars()).cloned());
+                    continue;
+                }
+                // Queries with other sources can't be profiled.
+                Step::Tuples(_) => continue,
+                Step::Goals => {
+                    let (new_bindings, stats) = self.measure(|| {
+                        let bindings = self.solve_goals(&query.rules, &query.goals, bindings)?;
+                        Ok(query::filter(&query.predicates, bindings))
+                    })?;
+                    rules = Some(stats);
+                    bindings = new_bindings;
+                    continue;
+                }
+            };
+            let (access, range) = access(clause, &bound, &query.predicates);
+            let plan = ClausePlan {
+                attribute: None,
+                access,
+                range,
+            };
+            let (new_bindings, stats) = self.measure(|| {
+                let bindings = self.match_clause(clause, Some(&plan), bindings)?;
+                Ok(query::filter(&query.predicates, bindings))
+            })?;
+            steps.push(ProfileStep {
+                           clause: clause.clone(),
+                           access,
+                           stats,
+                       });
+            bindings = new_bindings;
+            bound.extend(clause.vars().into_iter().cloned());
+        }
+
+
+        Ok(Profile {
+               steps,
+               rules,
+               result: self.project(query, bindings)?,
+           })
+    }
+
+    fn measure<F>(&self, step: F) -> Result<(Vec<Binding>, StepStats)>
+        where F: FnOnce() -> Result<Vec<Binding>>
+    {
+        let before = self.eav.cache_stats();
+        let start = Instant::now();
+        let bindings = step()?;
+        let stats = StepStats {
+            rows: bindings.len(),
+            cache: self.eav.cache_stats().since(before),
+            time: start.elapsed(),
+        };
+        Ok((bindings, stats))
+    }
+
+    /// About how many records each lookup for `clause` will read,
+    /// from at most `ESTIMATE_SAMPLE` of those in the range its
+    /// constants pick out, so that explaining a query never reads more
+    /// than a few nodes of each index.
+    fn estimate(&self, clause: &Clause, bound: &HashSet<Var>) -> Result<usize> {
+        if clause.source.is_some() {
+            return Ok(0);
+        }
+        let attr = match clause.attribute {
+            Term::Bound(ref a) => {
+                match self.idents.get_entity(a.clone()) {
+                    Some(attr) => Some(attr),
+                    None => return Ok(0),
+                }
+            }
+            Term::Unbound(_) => None,
+        };
+        let entity = match clause.entity {
+            Term::Bound(entity) => Some(entity),
+            Term::Unbound(_) => None,
+        };
+        let value = match clause.value {
+            Term::Bound(ref value) => Some(value),
+            Term::Unbound(_) => None,
+        };
+
+        let matches = |rec: &Record| {
+            entity.map_or(true, |e| e == rec.entity) && attr.map_or(true, |a| a == rec.attribute) &&
+            value.map_or(true, |v| *v == rec.value)
+        };
+        let sample = |records: Iter<Record>, in_range: &Fn(&Record) -> bool| {
+            take_while_ok(records.take(ESTIMATE_SAMPLE), in_range)
+        };
+        let empty = || Value::String("".into());
+        let records = match (entity, attr, value) {
+            (Some(e), _, _) => {
+                let start = Record::addition(e, attr.unwrap_or(Entity(0)), empty(), Entity(0));
+                sample(self.eav.iter_range_from(start..)?, &|rec| rec.entity == e)?
+            }
+            (None, Some(a), Some(v)) => {
+                let start = Record::addition(Entity(0), a, v.clone(), Entity(0));
+                sample(self.ave.iter_range_from(start..)?,
+                       &|rec| rec.attribute == a && rec.value == *v)?
+            }
+            (None, Some(a), None) => {
+                let start = Record::addition(Entity(0), a, empty(), Entity(0));
+                sample(self.aev.iter_range_from(start..)?, &|rec| rec.attribute == a)?
+            }
+            (None, None, _) => sample(self.eav.iter(), &|_| true)?,
+        };
+        let records = self.visible(records.into_iter().filter(|rec| matches(rec)));
+
+        let mut distinct = 1;
+        if is_bound_var(&clause.entity, bound) {
+            distinct *= records.iter().map(|rec| rec.entity).collect::<BTreeSet<_>>().len();
+        }
+        if is_bound_var(&clause.attribute, bound) {
+            distinct *= records.iter().map(|rec| rec.attribute).collect::<BTreeSet<_>>().len();
+        }
+        if is_bound_var(&clause.value, bound) {
+            distinct *= records.iter().map(|rec| &rec.value).collect::<BTreeSet<_>>().len();
+        }
+
+        Ok((records.len() + distinct - 1) / distinct.max(1))
+    }
+}
+
+/// The most records read to estimate the rows of a clause.
+const ESTIMATE_SAMPLE: usize = 1000;
+
+/// The access for `clause`, when the variables in `bound` are known,
+/// along with the predicates that bound its value if it's a range.
+pub(crate) fn access(clause: &Clause,
+                     bound: &HashSet<Var>,
+                     predicates: &[Predicate])
+                     -> (Access, Vec<Predicate>) {
+    let access = Access::choose(is_known(&clause.entity, bound),
+                                is_known(&clause.attribute, bound),
+                                is_known(&clause.value, bound));
+    let value = match (access, &clause.value) {
+        (Access::Aevt, &Term::Unbound(ref var)) => var,
+        _ => return (access, vec![]),
+    };
+
+    let range = predicates
+        .iter()
+        .filter(|p| p.bound_on(value).map_or(false, |(_, other)| is_known(other, bound)))
+        .cloned()
+        .collect::<Vec<_>>();
+    if range.is_empty() {
+        (access, range)
+    } else {
+        (Access::AvetRange, range)
+    }
+}
+
+/// Whether the term is a constant or a variable an earlier clause
+/// binds.
+fn is_known<T>(term: &Term<T>, bound: &HashSet<Var>) -> bool {
+    term.var().map_or(true, |var| bound.contains(var))
+}
+
+fn is_bound_var<T>(term: &Term<T>, bound: &HashSet<Var>) -> bool {
+    term.var().map_or(false, |var| bound.contains(var))
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let rows = self.steps
+            .iter()
+            .map(|step| {
+                     vec![step.clause.to_string(),
+                          step.access.to_string(),
+                          step.estimated_rows.to_string()]
+                 })
+            .collect_vec();
+        write!(f, "{}", table(&["clause", "index", "est. rows"], rows))?;
+
+        if !self.goals.is_empty() {
+            writeln!(f, "then rules: {}", self.goals.iter().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut rows = self.steps
+            .iter()
+            .map(|step| stats_row(step.clause.to_string(), step.access.to_string(), &step.stats))
+            .collect_vec();
+        if let Some(ref stats) = self.rules {
+            rows.push(stats_row("rules".into(), "".into(), stats));
+        }
+
+        write!(f,
+               "{}",
+               table(&["clause", "index", "rows", "nodes", "hits", "misses", "time"], rows))?;
+        write!(f, "{}", self.result)
+    }
+}
+
+fn stats_row(step: String, access: String, stats: &StepStats) -> Vec<String> {
+    let millis = stats.time.as_secs() as f64 * 1e3 + stats.time.subsec_nanos() as f64 / 1e6;
+    vec![step,
+         access,
+         stats.rows.to_string(),
+         stats.cache.nodes().to_string(),
+         stats.cache.hits.to_string(),
+         stats.cache.misses.to_string(),
+         format!("{:.3}ms", millis)]
+}
+
+fn table(titles: &[&str], rows: Vec<Vec<String>>) -> pt::Table {
+    let mut table = pt::Table::new();
+    table.set_titles(titles.iter().map(|t| t.to_string()).collect());
+    table.set_format(*pt::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    for row in rows {
+        table.add_row(row.into());
+    }
+    table
+}
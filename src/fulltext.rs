@@ -0,0 +1,227 @@
+//! Full-text search over the string values of attributes marked
+//! `db:fulltext`, as in `[(fulltext name "bob smith") [[?e ?v ?score]]]`.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use btree::Index;
+use db::{Db, take_while_ok};
+use {AVET, Entity, Float, Record, Result, Value};
+
+/// The words of `text`, lowercased, each once: the runs of letters and
+/// digits between anything else.
+pub(crate) fn tokens(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Adds a record for each token of `record`'s value to `index`,
+/// creating the index if this is the first value to go in it.
+/// Retractions aren't indexed, since another value can share their
+/// tokens: `search` checks the current values instead.
+pub(crate) fn index(db: &Db,
+                    index: Option<Index<Record, AVET>>,
+                    record: &Record)
+                    -> Result<Option<Index<Record, AVET>>> {
+    let text = match record.value {
+        Value::String(ref text) if !record.retracted => text,
+        _ => return Ok(index),
+    };
+    let mut index = match index {
+        Some(index) => index,
+        None => Index::empty(db.ave.node_store(), AVET)?,
+    };
+    for token in tokens(text) {
+        index = index.insert(Record { value: Value::String(token), ..record.clone() })?;
+    }
+    Ok(Some(index))
+}
+
+/// Adds the current values of `attr` to `index`, for when it's newly
+/// marked `db:fulltext`.
+pub(crate) fn index_attribute(db: &Db,
+                              mut index: Option<Index<Record, AVET>>,
+                              attr: Entity)
+                              -> Result<Option<Index<Record, AVET>>> {
+    let start = Record::addition(Entity(0), attr, Value::String("".into()), Entity(0));
+    let records = take_while_ok(db.aev.iter_range_from(start..)?, |rec| rec.attribute == attr)?;
+    for record in db.visible(records.into_iter()) {
+        index = self::index(db, index, &record)?;
+    }
+    Ok(index)
+}
+
+/// The attributes marked `db:fulltext`.
+pub(crate) fn attributes(db: &Db) -> Result<HashSet<Entity>> {
+    let flag = match db.idents.get_entity("db:fulltext".to_string()) {
+        Some(flag) => flag,
+        None => return Ok(HashSet::new()),
+    };
+    Ok(db.attribute_records(flag)?
+           .into_iter()
+           .filter(|rec| rec.value == Value::Boolean(true))
+           .map(|rec| rec.entity)
+           .collect())
+}
+
+impl Db {
+    /// Whether `attr` is marked `db:fulltext`, so that its string
+    /// values are indexed by token.
+    pub(crate) fn is_fulltext(&self, attr: Entity) -> Result<bool> {
+        match self.idents.get_entity("db:fulltext".to_string()) {
+            Some(flag) => {
+                Ok(self.attribute_values(attr, flag)?.contains(&Value::Boolean(true)))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// The values of `attr` containing any of the words in `text`,
+    /// each as a tuple of its entity, the value and the fraction of
+    /// the words it contains.
+    pub(crate) fn search(&self, attr: Entity, text: &str) -> Result<Vec<Vec<Value>>> {
+        let index = match self.fulltext {
+            Some(ref index) => index,
+            None => return Ok(vec![]),
+        };
+        let words = tokens(text);
+        let mut candidates = BTreeSet::new();
+        for word in &words {
+            let token = Value::String(word.clone());
+            let start = Record::addition(Entity(0), attr, token.clone(), Entity(0));
+            let records = take_while_ok(index.iter_range_from(start..)?,
+                                        |rec| rec.attribute == attr && rec.value == token)?;
+            candidates.extend(self.visible(records.into_iter()).into_iter().map(|rec| rec.entity));
+        }
+
+        // The index only says which entities had a word in some value
+        // of the attribute, so each current value is checked.
+        let mut scores = BTreeMap::new();
+        for entity in candidates {
+            for value in self.attribute_values(entity, attr)? {
+                let found = match value {
+                    Value::String(ref s) => tokens(s).intersection(&words).count(),
+                    _ => 0,
+                };
+                if found > 0 {
+                    let score = found as f64 / words.len() as f64;
+                    scores.insert((entity, value), score);
+                }
+            }
+        }
+        Ok(scores
+               .into_iter()
+               .map(|((entity, value), score)| {
+                        vec![Value::Entity(entity), value, Value::Float(Float(score))]
+                    })
+               .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use backends::mem::HeapStore;
+    use db::Conn;
+    use parse_tx;
+
+    /// A connection to a db whose `bio` attribute is marked
+    /// `db:fulltext`, along with the attribute.
+    fn bio_conn() -> (Conn, Entity) {
+        let conn = Conn::new(Arc::new(HeapStore::new::<Record>())).unwrap();
+        conn.transact(parse_tx("{db:ident db:fulltext} {db:ident name}").unwrap()).unwrap();
+        conn.transact(parse_tx("{db:ident bio db:fulltext true}").unwrap()).unwrap();
+        let bio = conn.db().unwrap().idents.get_entity("bio".to_string()).unwrap();
+        (conn, bio)
+    }
+
+    fn row(entity: u64, value: &str, score: f64) -> Vec<Value> {
+        vec![Value::Entity(Entity(entity)), Value::String(value.into()), Value::Float(Float(score))]
+    }
+
+    #[test]
+    fn test_tokens() {
+        let words = |ws: &[&str]| ws.iter().map(|w| w.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(tokens("Bob Smith, bob-the-builder!"),
+                   words(&["bob", "builder", "smith", "the"]));
+        assert_eq!(tokens(" ,; "), words(&[]));
+    }
+
+    #[test]
+    fn test_index() {
+        let (conn, bio) = bio_conn();
+        let db = conn.db().unwrap();
+        let tokens_of = |index: Option<Index<Record, AVET>>| {
+            let index = index.unwrap();
+            let start = Record::addition(Entity(0), bio, Value::String("".into()), Entity(0));
+            take_while_ok(index.iter_range_from(start..).unwrap(), |rec| rec.attribute == bio)
+                .unwrap()
+                .into_iter()
+                .map(|rec| rec.value)
+                .collect::<Vec<_>>()
+        };
+
+        let record = Record::addition(Entity(10), bio, Value::String("Red car".into()), Entity(9));
+        assert_eq!(tokens_of(index(&db, None, &record).unwrap()),
+                   vec![Value::String("car".into()), Value::String("red".into())]);
+        // Neither retractions nor values other than strings are indexed.
+        let retraction = Record { retracted: true, ..record.clone() };
+        assert!(index(&db, None, &retraction).unwrap().is_none());
+        let record = Record::addition(Entity(10), bio, Value::Integer(3), Entity(9));
+        assert!(index(&db, None, &record).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_search() {
+        let (conn, bio) = bio_conn();
+        conn.transact(parse_tx("add (0 bio \"Bob Smith, builder\") add (1 bio \"John Smith\") \
+                                add (2 bio \"Jane Doe\")")
+                              .unwrap())
+            .unwrap();
+        let db = conn.db().unwrap();
+
+        assert_eq!(db.search(bio, "bob SMITH").unwrap(),
+                   vec![row(0, "Bob Smith, builder", 1.0), row(1, "John Smith", 0.5)]);
+        assert_eq!(db.search(bio, "doe").unwrap(), vec![row(2, "Jane Doe", 1.0)]);
+        assert_eq!(db.search(bio, "nobody").unwrap(), Vec::<Vec<Value>>::new());
+
+        // Retracted values aren't found, and earlier views still see them.
+        conn.transact(parse_tx("retract (2 bio \"Jane Doe\") add (2 bio \"Jane Roe\")").unwrap())
+            .unwrap();
+        let after = conn.db().unwrap();
+        assert_eq!(after.search(bio, "doe").unwrap(), Vec::<Vec<Value>>::new());
+        assert_eq!(after.search(bio, "roe").unwrap(), vec![row(2, "Jane Roe", 1.0)]);
+        assert_eq!(db.search(bio, "doe").unwrap(), vec![row(2, "Jane Doe", 1.0)]);
+    }
+
+    #[test]
+    fn test_search_shared_words() {
+        let (conn, bio) = bio_conn();
+        conn.transact(parse_tx("add (3 bio \"red car\") add (3 bio \"red apple\")").unwrap())
+            .unwrap();
+        conn.transact(parse_tx("retract (3 bio \"red car\")").unwrap()).unwrap();
+        let db = conn.db().unwrap();
+
+        // Retracting one value leaves the other that shares its word.
+        assert_eq!(db.search(bio, "red").unwrap(), vec![row(3, "red apple", 1.0)]);
+        assert_eq!(db.search(bio, "car").unwrap(), Vec::<Vec<Value>>::new());
+    }
+
+    #[test]
+    fn test_index_attribute() {
+        let (conn, _) = bio_conn();
+        conn.transact(parse_tx("add (4 name \"John Smith\")").unwrap()).unwrap();
+        let name = conn.db().unwrap().idents.get_entity("name".to_string()).unwrap();
+        assert_eq!(conn.db().unwrap().search(name, "john").unwrap(), Vec::<Vec<Value>>::new());
+
+        // Marking an attribute db:fulltext indexes the values it
+        // already has.
+        conn.transact(parse_tx(&*format!("add ({} db:fulltext true)", name.0)).unwrap()).unwrap();
+        assert_eq!(conn.db().unwrap().search(name, "john").unwrap(),
+                   vec![row(4, "John Smith", 1.0)]);
+    }
+}
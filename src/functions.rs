@@ -0,0 +1,370 @@
+//! The built-in functions queries can call, as in
+//! `[(get-else ?e age 0) ?age]`, and when to call them.
+
+use std::collections::{HashMap, HashSet};
+
+use db::Db;
+use query::{BindForm, Call, Clause, Function, Query, Term, TuplePattern, Var};
+use {Binding, Entity, Error, Result, Value};
+
+/// A step of running a query.
+pub(crate) enum Step<'a> {
+    Match(&'a Clause),
+    /// Matching a tuple pattern against its relation.
+    Tuples(&'a TuplePattern),
+    Call(&'a Call),
+    /// Solving the query's goals with its rules.
+    Goals,
+}
+
+/// The order to run `query`'s steps in: its tuple patterns, its clauses
+/// in order, then its goals, with each call as soon as every variable
+/// it reads is bound. Relations are given along with the query, like
+/// its inputs, so the variables their patterns bind are known before
+/// the clauses are matched.
+pub(crate) fn schedule(query: &Query) -> Result<Vec<Step>> {
+    let mut bound = query.inputs.iter().cloned().collect::<HashSet<_>>();
+    let mut pending = query.calls.iter().collect::<Vec<_>>();
+    let mut steps = vec![];
+    for pattern in &query.patterns {
+        ready_calls(&mut pending, &mut bound, &mut steps);
+        steps.push(Step::Tuples(pattern));
+        bound.extend(pattern.vars().into_iter().cloned());
+    }
+    for clause in &query.clauses {
+        ready_calls(&mut pending, &mut bound, &mut steps);
+        steps.push(Step::Match(clause));
+        bound.extend(clause.vars().into_iter().cloned());
+    }
+    ready_calls(&mut pending, &mut bound, &mut steps);
+    if !query.goals.is_empty() {
+        steps.push(Step::Goals);
+        for goal in &query.goals {
+            bound.extend(goal.args.iter().filter_map(Term::var).cloned());
+        }
+        ready_calls(&mut pending, &mut bound, &mut steps);
+    }
+
+    match pending.first() {
+        Some(call) => {
+            Err(Error::Validation(format!("{} reads a variable that is never bound before it \
+                                           can be called",
+                                          call)))
+        }
+        None => Ok(steps),
+    }
+}
+
+/// Moves the calls whose inputs are all bound from `pending` to
+/// `steps`, along with any calls the variables they bind make ready.
+fn ready_calls<'a>(pending: &mut Vec<&'a Call>,
+                   bound: &mut HashSet<Var>,
+                   steps: &mut Vec<Step<'a>>) {
+    loop {
+        let ready = pending
+            .iter()
+            .position(|call| call.inputs().iter().all(|var| bound.contains(var)));
+        let call = match ready {
+            Some(i) => pending.remove(i),
+            None => return,
+        };
+        bound.extend(call.bind.iter().flat_map(BindForm::vars).cloned());
+        steps.push(Step::Call(call));
+    }
+}
+
+impl Db {
+    /// Extends each of `bindings` with the results of `call`, dropping
+    /// those it has no result for or whose results don't fit its bind
+    /// form.
+    pub(crate) fn call(&self, call: &Call, bindings: Vec<Binding>) -> Result<Vec<Binding>> {
+        let mut results = vec![];
+        for binding in bindings {
+            let args = call.args
+                .iter()
+                .map(|arg| match *arg {
+                         Term::Bound(ref value) => Ok(value.clone()),
+                         Term::Unbound(ref var) => {
+                             let unbound = || Error::Validation(format!("{} is unbound", var));
+                             binding.get(var).cloned().ok_or_else(unbound)
+                         }
+                     })
+                .collect::<Result<Vec<_>>>()?;
+            // An integer passed where an entity is expected names that
+            // entity, so the variable holding it is bound to the entity.
+            let mut binding = binding;
+            for &i in call.function.entity_args() {
+                let arg = call.args.get(i).and_then(Term::var);
+                if let (Some(var), Some(e)) = (arg, args.get(i).and_then(Value::as_entity)) {
+                    binding.insert(var.clone(), Value::Entity(e));
+                }
+            }
+            let result = match self.apply(call.function, args)? {
+                Some(result) => result,
+                None => continue,
+            };
+
+            match call.bind {
+                None if result == Value::Boolean(false) => {}
+                None => results.push(binding),
+                Some(ref form) => results.extend(bind(form, result, &binding, call)?),
+            }
+        }
+        self.check_limits(results.len())?;
+        Ok(results)
+    }
+
+    /// The result of `function`, or `None` if it has none for `args`.
+    fn apply(&self, function: Function, mut args: Vec<Value>) -> Result<Option<Value>> {
+        function.check_arity(args.len())?;
+        let result = match function {
+            Function::GetElse => {
+                let default = args.pop().unwrap();
+                let entity = entity_arg(function, &args[0])?;
+                let attr = self.attribute_arg(function, &args[1])?;
+                let values = self.attribute_values(entity, attr)?;
+                values.into_iter().next().unwrap_or(default)
+            }
+            Function::Missing => {
+                let entity = entity_arg(function, &args[0])?;
+                let attr = self.attribute_arg(function, &args[1])?;
+                let values = self.attribute_values(entity, attr)?;
+                Value::Boolean(values.is_empty())
+            }
+            Function::Tuple => Value::List(args),
+            Function::Untuple => {
+                match args.pop().unwrap() {
+                    list @ Value::List(_) => list,
+                    other => {
+                        return Err(Error::Validation(format!("untuple takes a tuple, not {}",
+                                                             other)))
+                    }
+                }
+            }
+            Function::Ground | Function::Identity => args.pop().unwrap(),
+            Function::Reachable => {
+                let from = entity_arg(function, &args[0])?;
+                let edge = self.edge_arg(function, &args[1])?;
+                let depth = match args.get(2) {
+                    Some(depth) => Some(depth_arg(function, depth)?),
+                    None => None,
+                };
+                let reached = self.traverse(from, edge, depth, None)?;
+                Value::List(reached.into_iter().map(|(e, _)| Value::Entity(e)).collect())
+            }
+            Function::ShortestPath => {
+                let from = entity_arg(function, &args[0])?;
+                let to = entity_arg(function, &args[1])?;
+                let edge = self.edge_arg(function, &args[2])?;
+                let depth = match args.get(3) {
+                    Some(depth) => Some(depth_arg(function, depth)?),
+                    None => None,
+                };
+                match self.shortest_path(from, to, edge, depth)? {
+                    Some(path) => Value::List(path.into_iter().map(Value::Entity).collect()),
+                    None => return Ok(None),
+                }
+            }
+            Function::Fulltext => {
+                let attr = self.attribute_arg(function, &args[0])?;
+                if !self.is_fulltext(attr)? {
+                    return Err(Error::Schema(format!("{} is not a db:fulltext attribute",
+                                                     args[0])));
+                }
+                match args[1] {
+                    Value::String(ref text) => {
+                        Value::List(self.search(attr, text)?.into_iter().map(Value::List).collect())
+                    }
+                    ref other => {
+                        return Err(Error::Validation(format!("fulltext searches for a string, \
+                                                              not {}",
+                                                             other)))
+                    }
+                }
+            }
+        };
+        Ok(Some(result))
+    }
+
+    /// The attribute `value` names, as an argument to `function`.
+    fn attribute_arg(&self, function: Function, value: &Value) -> Result<Entity> {
+        match *value {
+            Value::Ident(ref a) |
+            Value::String(ref a) => self.attribute_entity(a),
+            Value::Entity(a) => Ok(a),
+            ref other => {
+                Err(Error::Validation(format!("{} takes an attribute, not {}",
+                                              function.name(),
+                                              other)))
+            }
+        }
+    }
+
+    /// The attribute a traversal follows, and whether it follows it
+    /// backwards, as it does for `_parent` or `person/_parent`.
+    fn edge_arg(&self, function: Function, value: &Value) -> Result<Edge> {
+        let name = match *value {
+            Value::Ident(ref a) |
+            Value::String(ref a) => a,
+            _ => {
+                let attr = self.attribute_arg(function, value)?;
+                return Ok(Edge { attr, reverse: false });
+            }
+        };
+        let (ns, rest) = name.split_at(name.rfind('/').map_or(0, |i| i + 1));
+        if rest.len() > 1 && rest.starts_with('_') {
+            let attr = self.attribute_entity(&format!("{}{}", ns, &rest[1..]))?;
+            Ok(Edge { attr, reverse: true })
+        } else {
+            Ok(Edge {
+                   attr: self.attribute_entity(name)?,
+                   reverse: false,
+               })
+        }
+    }
+
+    /// Follows `edge` from `from` breadth first, at most `depth` times
+    /// if that's given, stopping early if it reaches `to`. Returns each
+    /// entity reached, in the order it was first reached, along with
+    /// the entity it was reached from. Each entity is only followed
+    /// once, so cycles end the search rather than looping.
+    fn traverse(&self,
+                from: Entity,
+                edge: Edge,
+                depth: Option<usize>,
+                to: Option<Entity>)
+                -> Result<Vec<(Entity, Entity)>> {
+        let mut reached = vec![];
+        let mut seen = HashSet::new();
+        let mut frontier = vec![from];
+        let mut steps = 0;
+        while !frontier.is_empty() && depth.map_or(true, |depth| steps < depth) {
+            self.check_limits(reached.len())?;
+            let mut next = vec![];
+            for entity in frontier {
+                for neighbour in self.neighbours(entity, edge)? {
+                    if !seen.insert(neighbour) {
+                        continue;
+                    }
+                    reached.push((neighbour, entity));
+                    if Some(neighbour) == to {
+                        return Ok(reached);
+                    }
+                    next.push(neighbour);
+                }
+            }
+            frontier = next;
+            steps += 1;
+        }
+        Ok(reached)
+    }
+
+    /// The entities on the shortest path from `from` to `to` along
+    /// `edge`, including both, if there is one.
+    fn shortest_path(&self,
+                     from: Entity,
+                     to: Entity,
+                     edge: Edge,
+                     depth: Option<usize>)
+                     -> Result<Option<Vec<Entity>>> {
+        if from == to {
+            return Ok(Some(vec![from]));
+        }
+        let reached = self.traverse(from, edge, depth, Some(to))?;
+        if reached.last().map(|&(e, _)| e) != Some(to) {
+            return Ok(None);
+        }
+
+        let previous = reached.into_iter().collect::<HashMap<_, _>>();
+        let mut path = vec![to];
+        let mut entity = to;
+        while entity != from {
+            entity = previous[&entity];
+            path.push(entity);
+        }
+        path.reverse();
+        Ok(Some(path))
+    }
+
+    /// The entities one step along `edge` from `entity`: those it
+    /// refers to through the EAVT index, or those that refer to it
+    /// through the AVET index if the edge is reversed.
+    fn neighbours(&self, entity: Entity, edge: Edge) -> Result<Vec<Entity>> {
+        if edge.reverse {
+            return self.referrers(entity, edge.attr);
+        }
+        Ok(self.attribute_values(entity, edge.attr)?
+               .iter()
+               .filter_map(|value| self.idents.resolve(value))
+               .collect())
+    }
+}
+
+/// An attribute followed by a traversal.
+#[derive(Clone, Copy)]
+struct Edge {
+    attr: Entity,
+    reverse: bool,
+}
+
+fn entity_arg(function: Function, value: &Value) -> Result<Entity> {
+    value.as_entity().ok_or_else(|| {
+        Error::Validation(format!("{} takes an entity, not {}", function.name(), value))
+    })
+}
+
+/// The most steps a traversal may take.
+fn depth_arg(function: Function, value: &Value) -> Result<usize> {
+    match *value {
+        Value::Integer(n) if n >= 0 => Ok(n as usize),
+        ref other => {
+            Err(Error::Validation(format!("{} takes a depth, not {}", function.name(), other)))
+        }
+    }
+}
+
+/// The extensions of `binding` that bind `form` to `result`.
+fn bind(form: &BindForm, result: Value, binding: &Binding, call: &Call) -> Result<Vec<Binding>> {
+    let tuples = match *form {
+        BindForm::Scalar(_) => vec![vec![result]],
+        BindForm::Tuple(_) => vec![list(result, call)?],
+        BindForm::Collection(_) => list(result, call)?.into_iter().map(|v| vec![v]).collect(),
+        BindForm::Relation(_) => {
+            list(result, call)?
+                .into_iter()
+                .map(|tuple| list(tuple, call))
+                .collect::<Result<_>>()?
+        }
+    };
+
+    let vars = form.vars();
+    let mut bindings = vec![];
+    'tuples: for tuple in tuples {
+        if tuple.len() != vars.len() {
+            return Err(Error::Validation(format!("{} returned a tuple of {} values for {}",
+                                                 call,
+                                                 tuple.len(),
+                                                 form)));
+        }
+        let mut extended = binding.clone();
+        for (var, value) in vars.iter().zip(tuple) {
+            let clashes = extended.get(*var).map(|existing| *existing != value);
+            match clashes {
+                Some(true) => continue 'tuples,
+                Some(false) => {}
+                None => {
+                    extended.insert((*var).clone(), value);
+                }
+            }
+        }
+        bindings.push(extended);
+    }
+    Ok(bindings)
+}
+
+fn list(value: Value, call: &Call) -> Result<Vec<Value>> {
+    match value {
+        Value::List(values) => Ok(values),
+        other => Err(Error::Validation(format!("{} returned {}, not a list", call, other))),
+    }
+}
@@ -1,4 +1,4 @@
-use model::Entity;
+use model::{Entity, Value};
 
 /// Double-mapping of ident->entity and entity->ident.
 // FIXME: Should this be two hashmaps? benchmark.
@@ -17,7 +17,7 @@ impl IdentMap {
     pub fn retract(&self, ident: &str, entity: Entity) -> IdentMap {
         IdentMap {
             mappings: self.mappings.iter()
-                .filter({|&&(ref i, e)| (i.as_str(), e) == (ident, entity)})
+                .filter(|&&(ref i, e)| (i.as_str(), e) != (ident, entity))
                 .cloned()
                 .collect::<Vec<_>> ()
         }
@@ -32,6 +32,36 @@ impl IdentMap {
         self.mappings.iter().find(|&&(_, e)| e == entity)
             .map(|&(ref i, _)| i.clone())
     }
+
+    /// The entity a value refers to, either directly, by id or by
+    /// ident.
+    pub fn resolve(&self, value: &Value) -> Option<Entity> {
+        match *value {
+            Value::Ident(ref i) => self.get_entity(i.clone()),
+            ref other => other.as_entity(),
+        }
+    }
+
+    /// The other ways of writing a reference to the same entity as
+    /// `value`: as the entity, its id or its ident.
+    pub fn aliases(&self, value: &Value) -> Vec<Value> {
+        let entity = match self.resolve(value) {
+            Some(entity) => entity,
+            None => return vec![],
+        };
+        let ident = self.get_ident(entity).map(Value::Ident);
+        vec![Value::Entity(entity), Value::Integer(entity.0 as i64)]
+            .into_iter()
+            .chain(ident)
+            .filter(|alias| alias != value)
+            .collect()
+    }
+
+    /// Whether two values are equal, counting an entity, its id and
+    /// its ident as equal.
+    pub fn same_value(&self, a: &Value, b: &Value) -> bool {
+        a == b || self.aliases(a).contains(b)
+    }
 }
 
 
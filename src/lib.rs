@@ -16,11 +16,15 @@ extern crate serde_derive;
 extern crate serde;
 extern crate rmp_serde;
 
+#[cfg(feature = "stores")]
 extern crate cdrs;
+#[cfg(feature = "stores")]
 extern crate rusqlite;
+#[cfg(feature = "stores")]
 extern crate r2d2;
 
 extern crate uuid;
+#[cfg(feature = "network")]
 extern crate zmq;
 
 #[macro_use]
@@ -41,34 +45,47 @@ pub mod string_ref;
 pub mod btree;
 pub mod backends;
 pub mod tx;
+pub mod error;
+pub mod pull;
+pub mod entity;
 mod query;
+mod rules;
+mod explain;
+mod prepared;
+mod cache;
+mod limits;
+mod sources;
+mod functions;
+mod fulltext;
+mod schema;
 mod rbtree;
 mod model;
 mod ident;
 
 pub use parser::*;
-pub use model::{Fact, Record, Value, Entity};
-use query::{Query, Clause, Term, Var};
+pub use error::{Abort, Error, Result};
+pub use model::{Fact, Record, Value, Entity, Float};
+pub use query::{Query, QueryBuilder, Clause, Term, Var, Atom, Rule, Predicate, Comparison, Call,
+                Function, BindForm, TuplePattern, var};
 use btree::{Index, Comparator};
 use backends::{KVStore};
 pub use ident::IdentMap;
+pub use pull::{PullPattern, PullAttr, AttrSpec};
+pub use entity::EntityHandle;
+pub use explain::{Access, Plan, PlanStep, Profile, ProfileStep, StepStats};
+pub use prepared::PreparedQuery;
+pub use cache::{CacheMetrics, ResultCache};
+pub use limits::{CancelToken, QueryOptions};
+pub use sources::Source;
+pub use schema::ValueType;
+#[cfg(feature = "stores")]
 use backends::cassandra::CassandraStore;
+#[cfg(feature = "stores")]
 use backends::sqlite::SqliteStore;
 use backends::mem::HeapStore;
 
 
-#[derive(Debug)]
-pub struct Error(String);
-
-impl<S: ToString> From<S> for Error {
-    fn from(other: S) -> Error {
-        Error(other.to_string())
-    }
-}
-
-pub type Result<T> = result::Result<T, Error>;
-
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct QueryResult(Vec<Var>, Vec<HashMap<Var, Value>>);
 
 impl Display for QueryResult {
@@ -121,22 +138,31 @@ pub enum TxItem {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum TxReport {
     Success {
+        /// The transaction entity, which carries the tx's `db:txInstant`
+        /// and can be given further attributes by later transactions.
+        tx: Entity,
         new_entities: Vec<Entity>
     },
-    Failure(String)
+    Failure(Error)
 }
 
 type Binding = HashMap<Var, Value>;
 
 impl Clause {
-    fn substitute(&self, env: &Binding) -> Result<Clause> {
+    fn substitute(&self, env: &Binding, idents: &IdentMap) -> Result<Clause> {
         let entity = match &self.entity {
             &Term::Bound(_) => self.entity.clone(),
             &Term::Unbound(ref var) => {
                 if let Some(val) = env.get(&var) {
-                    match *val {
-                        Value::Entity(e) => Term::Bound(e),
-                        _ => return Err("type mismatch".into()),
+                    // Attribute variables are bound to idents, which
+                    // can stand in for their entities.
+                    match idents.resolve(val) {
+                        Some(e) => Term::Bound(e),
+                        None => {
+                            return Err(Error::Validation(format!("?{} is {}, which is not an entity",
+                                                                 var.name,
+                                                                 val)))
+                        }
                     }
                 } else {
                     self.entity.clone()
@@ -148,9 +174,13 @@ impl Clause {
             &Term::Bound(_) => self.attribute.clone(),
             &Term::Unbound(ref var) => {
                 if let Some(val) = env.get(&var) {
-                    match val {
-                        &Value::String(ref s) => Term::Bound(s.clone()),
-                        _ => return Err("type mismatch".into()),
+                    match idents.resolve(val).and_then(|e| idents.get_ident(e)) {
+                        Some(ident) => Term::Bound(ident),
+                        None => {
+                            return Err(Error::Validation(format!("?{} is {}, which is not an attribute",
+                                                                 var.name,
+                                                                 val)))
+                        }
                     }
                 } else {
                     self.attribute.clone()
@@ -169,11 +199,60 @@ impl Clause {
             }
         };
 
-        Ok(Clause::new(entity, attribute, value))
+        let tx = match self.tx {
+            Some(Term::Unbound(ref var)) => {
+                match env.get(&var) {
+                    Some(&Value::Entity(e)) => Some(Term::Bound(e)),
+                    Some(val) => {
+                        return Err(Error::Validation(format!("?{} is {}, which is not a transaction",
+                                                             var.name,
+                                                             val)))
+                    }
+                    None => self.tx.clone(),
+                }
+            }
+            _ => self.tx.clone(),
+        };
+
+        let added = match self.added {
+            Some(Term::Unbound(ref var)) => {
+                match env.get(&var) {
+                    Some(val) => Some(Term::Bound(val.clone())),
+                    None => self.added.clone(),
+                }
+            }
+            _ => self.added.clone(),
+        };
+
+        Ok(Clause {
+               entity,
+               attribute,
+               value,
+               tx,
+               added,
+               source: self.source.clone(),
+           })
     }
 }
 
 
+/// How the fields of a record are ordered in the indexes.
+trait IndexOrd {
+    fn index_cmp(&self, other: &Self) -> std::cmp::Ordering;
+}
+
+impl IndexOrd for Entity {
+    fn index_cmp(&self, other: &Entity) -> std::cmp::Ordering {
+        self.cmp(other)
+    }
+}
+
+impl IndexOrd for Value {
+    fn index_cmp(&self, other: &Value) -> std::cmp::Ordering {
+        Value::index_cmp(self, other)
+    }
+}
+
 macro_rules! comparator {
     ($name:ident, $first:ident, $second:ident, $third:ident, $fourth:ident) => {
         #[derive(Debug, Clone)]
@@ -183,10 +262,10 @@ macro_rules! comparator {
             type Item = Record;
 
             fn compare(a: &Record, b: &Record) -> std::cmp::Ordering {
-                a.$first.cmp(&b.$first)
-                    .then(a.$second.cmp(&b.$second))
-                    .then(a.$third.cmp(&b.$third))
-                    .then(a.$fourth.cmp(&b.$fourth))
+                IndexOrd::index_cmp(&a.$first, &b.$first)
+                    .then(IndexOrd::index_cmp(&a.$second, &b.$second))
+                    .then(IndexOrd::index_cmp(&a.$third, &b.$third))
+                    .then(IndexOrd::index_cmp(&a.$fourth, &b.$fourth))
                     // retracted is always last
                     .then(a.retracted.cmp(&b.retracted))
             }
@@ -0,0 +1,128 @@
+//! Deadlines, budgets and cancellation for queries that might run
+//! for too long.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use db::Db;
+use query::Query;
+use {Abort, Error, QueryResult, Result};
+
+/// Lets another thread stop a running query. Clones share the same
+/// flag, so cancelling any of them cancels them all.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Limits on what a query may do before it's aborted with
+/// `Error::Aborted`. Nothing is limited by default.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    pub deadline: Option<Instant>,
+    /// The most bindings the query may have after any one step.
+    pub max_bindings: Option<usize>,
+    /// The most B-tree nodes the query may read.
+    pub max_nodes: Option<usize>,
+    pub cancel: Option<CancelToken>,
+}
+
+impl QueryOptions {
+    pub fn new() -> QueryOptions {
+        QueryOptions::default()
+    }
+
+    pub fn deadline(self, deadline: Instant) -> QueryOptions {
+        QueryOptions { deadline: Some(deadline), ..self }
+    }
+
+    /// A deadline `timeout` from now.
+    pub fn timeout(self, timeout: Duration) -> QueryOptions {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    pub fn max_bindings(self, max: usize) -> QueryOptions {
+        QueryOptions { max_bindings: Some(max), ..self }
+    }
+
+    pub fn max_nodes(self, max: usize) -> QueryOptions {
+        QueryOptions { max_nodes: Some(max), ..self }
+    }
+
+    pub fn cancel_on(self, token: CancelToken) -> QueryOptions {
+        QueryOptions { cancel: Some(token), ..self }
+    }
+}
+
+/// The options of the query a `Db` is running.
+#[derive(Debug)]
+pub(crate) struct Limits {
+    options: QueryOptions,
+    /// The B-tree nodes the query has read so far.
+    nodes: Arc<AtomicUsize>,
+}
+
+impl Db {
+    /// Runs `query`, aborting with `Error::Aborted` if it breaks any of
+    /// `options`.
+    pub fn query_with_options(&self, query: &Query, options: &QueryOptions) -> Result<QueryResult> {
+        self.with_options(options).query(query)
+    }
+
+    /// This db, but aborting whatever is run on it, such as a query,
+    /// its explanation or its profile, with `Error::Aborted` if it
+    /// breaks any of `options`.
+    pub fn with_options(&self, options: &QueryOptions) -> Db {
+        let nodes = Arc::new(AtomicUsize::new(0));
+        let mut db = self.clone();
+        db.eav = self.eav.counting_reads(nodes.clone());
+        db.ave = self.ave.counting_reads(nodes.clone());
+        db.aev = self.aev.counting_reads(nodes.clone());
+        db.fulltext = self.fulltext.as_ref().map(|index| index.counting_reads(nodes.clone()));
+        db.limits = Some(Arc::new(Limits {
+                                      options: options.clone(),
+                                      nodes,
+                                  }));
+        db
+    }
+
+    /// Fails if the running query has been cancelled or has broken one
+    /// of its limits, given that it has `bindings` bindings so far.
+    pub(crate) fn check_limits(&self, bindings: usize) -> Result<()> {
+        let limits = match self.limits {
+            Some(ref limits) => limits,
+            None => return Ok(()),
+        };
+        let options = &limits.options;
+
+        let abort = if options.cancel.as_ref().map_or(false, |t| t.is_cancelled()) {
+            Some(Abort::Cancelled)
+        } else if options.max_bindings.map_or(false, |max| bindings > max) {
+            options.max_bindings.map(Abort::Bindings)
+        } else if options.max_nodes.map_or(false, |max| limits.nodes.load(Ordering::SeqCst) > max) {
+            options.max_nodes.map(Abort::Nodes)
+        } else if options.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            Some(Abort::Deadline)
+        } else {
+            None
+        };
+
+        match abort {
+            Some(abort) => Err(Error::Aborted(abort)),
+            None => Ok(()),
+        }
+    }
+}
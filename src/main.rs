@@ -1,3 +1,4 @@
+extern crate ctrlc;
 extern crate logos;
 extern crate rustyline;
 
@@ -6,6 +7,7 @@ use logos::db::{Conn, store_from_uri};
 
 use std::error::Error;
 use std::env::args;
+use std::sync::{Arc, Mutex};
 
 fn run(uri: &str) {
     println!("
@@ -18,6 +20,23 @@ Commands:
     let store = store_from_uri(uri).expect("Couldn't create store");
     let conn = Conn::new(store.clone()).expect("Couldn't connect to DB -- does it exist?");
     let mut rl = rustyline::Editor::<()>::new();
+
+    // Ctrl-C at the prompt is handled by rustyline; while a query is
+    // running it cancels the query instead of exiting.
+    let running = Arc::new(Mutex::new(CancelToken::new()));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.lock().unwrap().cancel())
+            .expect("Couldn't set Ctrl-C handler");
+    }
+    let options = || {
+        let token = CancelToken::new();
+        *running.lock().unwrap() = token.clone();
+        QueryOptions::new().cancel_on(token)
+    };
+
+    // Rules defined in Datalog input stay defined for the rest of the session.
+    let mut rules = vec![];
     loop {
         let readline = rl.readline("> ");
         match readline {
@@ -30,20 +49,52 @@ Commands:
                 match parse_input(&*line) {
                     Ok(Input::Query(q)) => {
                         let db = conn.db().unwrap();
-                        match db.query(&q) {
+                        match db.query_with_options(&q, &options()) {
                             Ok(res) => println!("{}", res),
-                            Err(e) => println!("ERROR: {:?}", e),
+                            Err(e) => println!("ERROR: {}", e),
                         }
                     }
                     Ok(Input::Tx(tx)) => {
                         match conn.transact(tx) {
                             Ok(report) => println!("{:?}", report),
-                            Err(e) => println!("ERROR: {:?}", e),
+                            Err(e) => println!("ERROR: {}", e),
+                        }
+                    }
+                    Ok(Input::Program(program)) => {
+                        if !program.tx.items.is_empty() {
+                            match conn.transact(program.tx) {
+                                Ok(report) => println!("{:?}", report),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                        rules.extend(program.rules);
+
+                        let db = conn.db().unwrap();
+                        for mut q in program.queries {
+                            q.rules = rules.clone();
+                            match db.query_with_options(&q, &options()) {
+                                Ok(res) => println!("{}", res),
+                                Err(e) => println!("ERROR: {}", e),
+                            }
+                        }
+                    }
+                    Ok(Input::Explain(q)) => {
+                        match conn.db().and_then(|db| db.with_options(&options()).explain(&q)) {
+                            Ok(plan) => println!("{}", plan),
+                            Err(e) => println!("ERROR: {}", e),
+                        }
+                    }
+                    Ok(Input::Profile(q)) => {
+                        match conn.db().and_then(|db| db.with_options(&options()).profile(&q)) {
+                            Ok(profile) => println!("{}", profile),
+                            Err(e) => println!("ERROR: {}", e),
                         }
                     }
                     Ok(Input::SampleDb) => {
                         let sample = [
-                            r#"{db:ident name} {db:ident parent}"#,
+                            r#"{db:ident db:valueType}"#,
+                            r#"{db:ident name db:valueType string}
+                               {db:ident parent db:valueType ref}"#,
                             // FIXME: Don't hardcode entities; need a way to get the entity id of a tx
                             // (tempid system?)
                             r#"add (0 name "Bob")"#,
@@ -63,7 +114,7 @@ Commands:
                                                    .unwrap())
                                      .unwrap())
                     }
-                    Err(e) => println!("Oh no! {}", e),
+                    Err(e) => println!("ERROR: {}", e),
                 };
             }
             Err(e) => println!("Error! {:?}", e.description()),
@@ -1,4 +1,5 @@
-use std::collections::Bound;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, Bound};
 use std::collections::range::RangeArgument;
 use std::fmt;
 use std::fmt::{Formatter, Display};
@@ -78,7 +79,106 @@ pub enum Value {
     Ident(String),
     Entity(Entity),
     // FIXME: clock drift is an issue here
-    Timestamp(DateTime<UTC>)
+    Timestamp(DateTime<UTC>),
+    Boolean(bool),
+    // Composite values, as produced by pull patterns.
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Integer(i64),
+    Float(Float),
+}
+
+impl Value {
+    /// The order values have in the indexes. Values are grouped by
+    /// type, but numbers of every type are ordered together by value,
+    /// so that a range of numbers is a range of an index. Equal numbers
+    /// of different types are ordered floats, then integers, then
+    /// entities.
+    pub fn index_cmp(&self, other: &Value) -> Ordering {
+        match (self.number(), other.number()) {
+            (Some(x), Some(y)) => {
+                x.cmp(&y)
+                    .then(self.type_rank().cmp(&other.type_rank()))
+                    .then_with(|| self.cmp(other))
+            }
+            _ => self.type_rank().cmp(&other.type_rank()).then_with(|| self.cmp(other)),
+        }
+    }
+
+    /// Compares values as query predicates do: numbers of any type by
+    /// value, and anything else only with values of the same type.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self.number(), other.number()) {
+            (Some(_), Some(_)) if self.type_rank() == other.type_rank() => Some(self.cmp(other)),
+            (Some(x), Some(y)) => Some(x.cmp(&y)),
+            _ if self.type_rank() == other.type_rank() => Some(self.cmp(other)),
+            _ => None,
+        }
+    }
+
+    /// The entity the value names where an entity is expected: an
+    /// entity, or a non-negative integer as written in a query or
+    /// transaction.
+    pub fn as_entity(&self) -> Option<Entity> {
+        match *self {
+            Value::Entity(e) => Some(e),
+            Value::Integer(n) if n >= 0 => Some(Entity(n as u64)),
+            _ => None,
+        }
+    }
+
+    /// The value as a float, if it's a number. Entities count as
+    /// numbers, so that they can be compared with the integers that
+    /// stand for them.
+    pub(crate) fn number(&self) -> Option<Float> {
+        match *self {
+            Value::Entity(Entity(e)) => Some(Float(e as f64)),
+            Value::Integer(n) => Some(Float(n as f64)),
+            Value::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match *self {
+            Value::String(_) => 0,
+            Value::Ident(_) => 1,
+            Value::Float(_) => 2,
+            Value::Integer(_) => 3,
+            Value::Entity(_) => 4,
+            Value::Timestamp(_) => 5,
+            Value::Boolean(_) => 6,
+            Value::List(_) => 7,
+            Value::Map(_) => 8,
+        }
+    }
+}
+
+/// An `f64` that can be stored in the indexes, which need a total
+/// order. NaN sorts after every other number.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Float) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Float) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Float) -> Ordering {
+        self.0
+            .partial_cmp(&other.0)
+            .unwrap_or_else(|| self.0.is_nan().cmp(&other.0.is_nan()))
+    }
 }
 
 impl Display for Value {
@@ -87,7 +187,21 @@ impl Display for Value {
             Value::Entity(e) => format!("{}", e.0),
             Value::String(ref s) => format!("\"{}\"", s),
             Value::Ident(ref s) => format!("{}", s),
-            Value::Timestamp(t) => format!("{}", t)
+            Value::Timestamp(t) => format!("{}", t),
+            Value::Boolean(b) => format!("{}", b),
+            Value::Integer(i) => format!("{}", i),
+            // Debug formatting keeps the decimal point on whole numbers.
+            Value::Float(Float(x)) => format!("{:?}", x),
+            Value::List(ref vs) => {
+                format!("[{}]", vs.iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join(" "))
+            }
+            Value::Map(ref m) => {
+                format!("{{{}}}",
+                        m.iter()
+                            .map(|(k, v)| format!("{} {}", k, v))
+                            .collect::<Vec<_>>()
+                            .join(" "))
+            }
         })
     }
 }
@@ -106,5 +220,11 @@ impl From<Entity> for Value {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
 pub struct Entity(pub u64);
+
+impl Display for Entity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
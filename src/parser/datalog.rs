@@ -0,0 +1,213 @@
+//! Classic Datalog, as in `ancestor(A, B) :- parent(A, B).` A
+//! program is a series of statements, each of which is a fact to
+//! transact (`name(0, "Bob").`), a rule (`head :- body.`) or a query
+//! (`ancestor(0, B)?`). Words starting with a capital letter, and
+//! `_`, are variables.
+
+use std::result;
+
+use combine::primitives::{Error, SourcePosition, Stream};
+use combine::{Parser, eof, many, optional, position, sep_by1};
+
+use {Fact, Tx, TxItem, Value};
+use query::{Atom, Query, Rule, Term, Var};
+use super::{run, value, start_of, ParseError};
+use super::lexer::{ident, lex_char, symbol};
+
+/// The facts, rules and queries of a Datalog program.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    /// Additions for every fact in the program.
+    pub tx: Tx,
+    pub rules: Vec<Rule>,
+    /// A query for each `atom?`, with every rule in the program.
+    pub queries: Vec<Query>,
+}
+
+enum Statement {
+    Fact(Atom),
+    Rule(Rule),
+    Query(Atom),
+}
+
+/// Whether `input` looks like Datalog: it starts with a predicate
+/// followed immediately by `(`. `add (...)` and `retract (...)` are
+/// transactions in our own syntax.
+pub fn is_datalog(input: &str) -> bool {
+    let rest = start_of(input);
+    let word_len = rest.find(|c: char| !c.is_alphanumeric() && !"-_/:".contains(c))
+        .unwrap_or(rest.len());
+    let word = &rest[..word_len];
+
+    rest.starts_with(char::is_alphabetic) && rest[word_len..].starts_with('(') &&
+    word != "add" && word != "retract"
+}
+
+pub fn parse_program(input: &str) -> result::Result<Program, ParseError> {
+    let statements = run(many::<Vec<_>, _>(statement()).skip(eof()), input)?;
+
+    let mut tx = Tx { items: vec![] };
+    let mut rules = vec![];
+    let mut goals = vec![];
+    let mut blanks = 0;
+    for (pos, statement) in statements {
+        match statement {
+            Statement::Fact(atom) => {
+                let item = to_addition(atom).ok_or_else(|| {
+                    ParseError::at(input,
+                                   pos.line as usize,
+                                   pos.column as usize,
+                                   Some("a fact with variables".into()),
+                                   vec!["attr(entity, value).".into()])
+                })?;
+                tx.items.push(item);
+            }
+            Statement::Rule(mut rule) => {
+                name_blanks(&mut rule.head, &mut blanks);
+                for atom in &mut rule.body {
+                    name_blanks(atom, &mut blanks);
+                }
+                rules.push(rule);
+            }
+            Statement::Query(mut atom) => {
+                name_blanks(&mut atom, &mut blanks);
+                goals.push(atom);
+            }
+        }
+    }
+
+    let queries = goals
+        .into_iter()
+        .map(|goal| {
+            let mut find: Vec<Var> = vec![];
+            for arg in &goal.args {
+                if let Term::Unbound(ref var) = *arg {
+                    if !var.name.starts_with('_') && !find.contains(var) {
+                        find.push(var.clone());
+                    }
+                }
+            }
+
+            let mut query = Query::new(find, vec![]);
+            query.rules = rules.clone();
+            query.goals = vec![goal];
+            query
+        })
+        .collect();
+
+    Ok(Program { tx, rules, queries })
+}
+
+fn statement<I>() -> impl Parser<Input = I, Output = (SourcePosition, Statement)>
+    where I: Stream<Item = char, Position = SourcePosition>
+{
+    let body = symbol(":-").with(sep_by1(atom(), lex_char(',')));
+    let end = lex_char('.').map(|_| false).or(lex_char('?').map(|_| true));
+
+    (position(), atom(), optional(body), end).and_then(|(pos, head, body, is_query)| {
+        let statement = match (body, is_query) {
+            (Some(body), false) => Statement::Rule(Rule { head, body }),
+            (None, false) => Statement::Fact(head),
+            (None, true) => Statement::Query(head),
+            (Some(_), true) => return Err(Error::Message("a rule can't be a query".into())),
+        };
+        Ok((pos, statement))
+    })
+}
+
+fn atom<I>() -> impl Parser<Input = I, Output = Atom>
+    where I: Stream<Item = char>
+{
+    let term = lex_char('_')
+        .map(|_| Term::Unbound(Var::new("_")))
+        .or(value().map(|v| match v {
+                            Value::Ident(ref name) if name.starts_with(char::is_uppercase) => {
+                                Term::Unbound(Var::new(name.clone()))
+                            }
+                            v => Term::Bound(v),
+                        }));
+
+    ident()
+        .skip(lex_char('('))
+        .and(sep_by1(term, lex_char(',')))
+        .skip(lex_char(')'))
+        .map(|(predicate, args)| Atom::new(predicate, args))
+}
+
+fn to_addition(atom: Atom) -> Option<TxItem> {
+    match atom.args[..] {
+        [Term::Bound(ref e), Term::Bound(ref v)] => {
+            e.as_entity()
+                .map(|e| TxItem::Addition(Fact::new(e, atom.predicate.clone(), v.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// Gives each `_` a variable of its own.
+fn name_blanks(atom: &mut Atom, blanks: &mut usize) {
+    for arg in &mut atom.args {
+        if *arg == Term::Unbound(Var::new("_")) {
+            *blanks += 1;
+            *arg = Term::Unbound(Var::new(format!("_{}", blanks)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Entity;
+
+    #[test]
+    fn test_parse_program() {
+        let program = parse_program(r#"
+            name(0, "Bob"). name(1, "John"). parent(1, 0).
+            ; A person's ancestors are their parents and their parents' ancestors.
+            ancestor(A, B) :- parent(A, B).
+            ancestor(A, B) :- parent(A, C), ancestor(C, B).
+            ancestor(1, B)?
+        "#)
+                .unwrap();
+
+        assert_eq!(program.tx.items[2],
+                   TxItem::Addition(Fact::new(Entity(1), "parent", Value::Integer(0))));
+        assert_eq!(program.rules.len(), 2);
+        assert_eq!(program.rules[1].body,
+                   vec![Atom::new("parent", vec![Term::Unbound("A".into()), Term::Unbound("C".into())]),
+                        Atom::new("ancestor", vec![Term::Unbound("C".into()), Term::Unbound("B".into())])]);
+
+        let query = &program.queries[0];
+        assert_eq!(query.find, vec![Var::new("B")]);
+        assert_eq!(query.goals,
+                   vec![Atom::new("ancestor",
+                                  vec![Term::Bound(Value::Integer(1)),
+                                       Term::Unbound("B".into())])]);
+        assert_eq!(query.rules, program.rules);
+    }
+
+    #[test]
+    fn test_blanks() {
+        let program = parse_program("named(A) :- name(A, _), parent(A, _). parent(_, B)?").unwrap();
+        assert_eq!(program.rules[0].body[0].args[1], Term::Unbound("_1".into()));
+        assert_eq!(program.rules[0].body[1].args[1], Term::Unbound("_2".into()));
+        assert_eq!(program.queries[0].find, vec![Var::new("B")]);
+    }
+
+    #[test]
+    fn test_program_errors() {
+        let err = parse_program("name(0, \"Bob\").\nname(A, \"Jim\").").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+
+        assert!(parse_program("q(A) :- name(A, \"Bob\")?").is_err());
+    }
+
+    #[test]
+    fn test_detection() {
+        assert!(is_datalog("name(0, \"Bob\")."));
+        assert!(is_datalog("; comment\nancestor(A, B)?"));
+        assert!(!is_datalog("add (0 name \"Bob\")"));
+        assert!(!is_datalog("add(0 name \"Bob\")"));
+        assert!(!is_datalog("find ?a where (?a name \"Bob\")"));
+    }
+}
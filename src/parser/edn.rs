@@ -0,0 +1,874 @@
+//! Datomic-style queries and transactions written in EDN, such as
+//! `[:find ?a :where [?a :name "Bob"]]` and
+//! `[[:db/add 0 :name "Bob"] {:name "Jane"}]`. Input is read into
+//! generic EDN forms first and then converted, so that errors can
+//! point at the form that doesn't fit.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::result;
+
+use combine::char::{char, string};
+use combine::primitives::{Error, ParseResult, SourcePosition, Stream};
+use combine::{Parser, between, eof, many, many1, parser, position, satisfy, skip_many, try};
+
+use {Entity, Fact, Float, Tx, TxItem, Value};
+use query::{BindForm, Call, Clause, Comparison, Function, Predicate, Query, Term, TuplePattern,
+            Var};
+use pull::{AttrSpec, PullAttr, PullPattern};
+use super::{run, start_of, Input, ParseError};
+use super::lexer::{string_lit, whitespace};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Edn {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Keyword(String),
+    Symbol(String),
+    List(Vec<Form>),
+    Vector(Vec<Form>),
+    Map(Vec<(Form, Form)>),
+    Set(Vec<Form>),
+}
+
+/// An EDN value and where it starts in the input.
+#[derive(Debug, Clone, PartialEq)]
+struct Form {
+    edn: Edn,
+    line: usize,
+    column: usize,
+}
+
+/// Whether `input` looks like EDN rather than our own syntax: it
+/// starts with a vector, or with a map whose first key is a keyword.
+pub fn is_edn(input: &str) -> bool {
+    let rest = start_of(input);
+    rest.starts_with('[') || (rest.starts_with('{') && rest[1..].trim_left().starts_with(':'))
+}
+
+pub fn parse_input(input: &str) -> result::Result<Input, ParseError> {
+    let form = read(input)?;
+    let is_query = match form.edn {
+        Edn::Vector(ref items) => items.first().map_or(false, |f| is_keyword(f, "find")),
+        Edn::Map(ref entries) => entries.iter().any(|&(ref k, _)| is_keyword(k, "find")),
+        _ => false,
+    };
+
+    let mut converter = Converter::new(input);
+    if is_query {
+        converter.query(&form).map(Input::Query)
+    } else {
+        converter.tx(&form).map(Input::Tx)
+    }
+}
+
+pub fn parse_query(input: &str) -> result::Result<Query, ParseError> {
+    Converter::new(input).query(&read(input)?)
+}
+
+pub fn parse_tx(input: &str) -> result::Result<Tx, ParseError> {
+    Converter::new(input).tx(&read(input)?)
+}
+
+fn read(input: &str) -> result::Result<Form, ParseError> {
+    run(form().skip(eof()), input)
+}
+
+/// Commas are whitespace in EDN.
+fn separators<I>() -> impl Parser<Input = I, Output = ()>
+    where I: Stream<Item = char>
+{
+    whitespace().with(skip_many(char(',').with(whitespace())))
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || ".*+!-_?$%&=<>/:'".contains(c)
+}
+
+fn form<I>() -> impl Parser<Input = I, Output = Form>
+    where I: Stream<Item = char, Position = SourcePosition>
+{
+    // Forms nest, so this has to go through a plain function.
+    parser(form_)
+}
+
+fn form_<I>(input: I) -> ParseResult<Form, I>
+    where I: Stream<Item = char, Position = SourcePosition>
+{
+    let seq = |open: &'static str, close: char| {
+        between(try(string(open)).skip(separators()), char(close), many(form()))
+    };
+    let map = between(char('{').skip(separators()), char('}'), many(form().and(form())));
+    let atom = many1(satisfy(is_symbol_char))
+        .and_then(|s: String| read_atom(&s).map_err(|msg| Error::Message(msg.into())));
+
+    let edn = choice!(string_lit().map(Edn::String),
+                      seq("[", ']').map(Edn::Vector),
+                      seq("(", ')').map(Edn::List),
+                      seq("#{", '}').map(Edn::Set),
+                      map.map(Edn::Map),
+                      atom);
+
+    (position(), edn)
+        .skip(separators())
+        .map(|(pos, edn): (SourcePosition, Edn)| {
+                 Form {
+                     edn,
+                     line: pos.line as usize,
+                     column: pos.column as usize,
+                 }
+             })
+        .parse_stream(input)
+}
+
+/// Reads a symbol, keyword, number, boolean or nil.
+fn read_atom(s: &str) -> result::Result<Edn, &'static str> {
+    let digits = s.trim_left_matches(|c| c == '-' || c == '+');
+    let numeric = digits.len() + 1 >= s.len() && digits.starts_with(|c: char| c.is_digit(10));
+
+    if s.starts_with(':') {
+        if s.len() == 1 {
+            Err("empty keyword")
+        } else {
+            Ok(Edn::Keyword(s[1..].to_string()))
+        }
+    } else if numeric {
+        if s.contains(|c| c == '.' || c == 'e' || c == 'E') {
+            s.parse().map(Edn::Float).map_err(|_| "invalid number")
+        } else {
+            s.parse().map(Edn::Integer).map_err(|_| "invalid number")
+        }
+    } else {
+        Ok(match s {
+               "nil" => Edn::Nil,
+               "true" => Edn::Boolean(true),
+               "false" => Edn::Boolean(false),
+               _ => Edn::Symbol(s.to_string()),
+           })
+    }
+}
+
+impl Display for Edn {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let join = |forms: &[Form]| {
+            forms.iter().map(|f| format!("{}", f.edn)).collect::<Vec<_>>().join(" ")
+        };
+        match *self {
+            Edn::Nil => write!(f, "nil"),
+            Edn::Boolean(b) => write!(f, "{}", b),
+            Edn::Integer(i) => write!(f, "{}", i),
+            Edn::Float(x) => write!(f, "{:?}", x),
+            Edn::String(ref s) => write!(f, "{:?}", s),
+            Edn::Keyword(ref k) => write!(f, ":{}", k),
+            Edn::Symbol(ref s) => write!(f, "{}", s),
+            Edn::List(ref forms) => write!(f, "({})", join(forms)),
+            Edn::Vector(ref forms) => write!(f, "[{}]", join(forms)),
+            Edn::Set(ref forms) => write!(f, "#{{{}}}", join(forms)),
+            Edn::Map(ref entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|&(ref k, ref v)| format!("{} {}", k.edn, v.edn))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", entries.join(" "))
+            }
+        }
+    }
+}
+
+/// The call in a `:where` item that's a predicate like `[(> ?a 30)]`
+/// or a function call like `[(get-else $ ?e :age 0) ?age]` rather
+/// than a clause, along with the binding form of a function call.
+fn predicate_call(form: &Form) -> Option<(&Form, Option<&Form>)> {
+    match form.edn {
+        Edn::Vector(ref items) if items.len() == 1 || items.len() == 2 => {
+            match items[0].edn {
+                Edn::List(_) => Some((&items[0], items.get(1))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the call is to a comparison, as in `(> ?a 30)`.
+fn is_comparison(call: &Form) -> bool {
+    match call.edn {
+        Edn::List(ref items) => {
+            match items.first().map(|f| &f.edn) {
+                Some(&Edn::Symbol(ref s)) => Comparison::from_symbol(s).is_some(),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn is_keyword(form: &Form, name: &str) -> bool {
+    match form.edn {
+        Edn::Keyword(ref k) => k == name,
+        _ => false,
+    }
+}
+
+type Result<T> = result::Result<T, ParseError>;
+
+/// Turns EDN forms into queries and transactions.
+struct Converter<'a> {
+    input: &'a str,
+    // Used to name the variables that stand in for `_`.
+    blanks: usize,
+}
+
+impl<'a> Converter<'a> {
+    fn new(input: &'a str) -> Converter<'a> {
+        Converter { input, blanks: 0 }
+    }
+
+    fn error(&self, form: &Form, expected: &[&str]) -> ParseError {
+        ParseError::at(self.input,
+                       form.line,
+                       form.column,
+                       Some(form.edn.to_string()),
+                       expected.iter().map(|e| e.to_string()).collect())
+    }
+
+    /// `[:find ... :where ...]` or `{:find [...] :where [...]}`.
+    fn query(&mut self, form: &Form) -> Result<Query> {
+        let sections = match form.edn {
+            Edn::Vector(ref items) => self.sections(items)?,
+            Edn::Map(ref entries) => {
+                let mut sections = vec![];
+                for &(ref key, ref value) in entries {
+                    match value.edn {
+                        Edn::Vector(ref items) => sections.push((key, &items[..])),
+                        _ => return Err(self.error(value, &["[...]"])),
+                    }
+                }
+                sections
+            }
+            _ => return Err(self.error(form, &["[:find ...]", "{:find ...}"])),
+        };
+
+        let mut find = None;
+        let mut inputs = vec![];
+        let mut sources = vec![];
+        let mut clauses = None;
+        let mut predicates = vec![];
+        let mut calls = vec![];
+        let mut patterns = vec![];
+        for (key, items) in sections {
+            if is_keyword(key, "find") {
+                find = Some(items
+                                .iter()
+                                .map(|f| self.find_elem(f))
+                                .collect::<Result<Vec<_>>>()?);
+            } else if is_keyword(key, "in") {
+                for item in items {
+                    match as_source(item) {
+                        // `$` is the database, which is always the one queried.
+                        Some(ref name) if name.is_empty() => {}
+                        Some(name) => sources.push(name),
+                        None => {
+                            let var = as_var(item).ok_or_else(|| self.error(item, &["?var"]))?;
+                            inputs.push(var);
+                        }
+                    }
+                }
+            } else if is_keyword(key, "where") {
+                let mut where_clauses = vec![];
+                for item in items {
+                    match predicate_call(item) {
+                        Some((call, None)) if is_comparison(call) => {
+                            predicates.push(self.predicate(call)?)
+                        }
+                        Some((call, bind)) => calls.push(self.call(call, bind)?),
+                        None => {
+                            match self.pattern(item)? {
+                                Some(pattern) => patterns.push(pattern),
+                                None => where_clauses.push(self.clause(item)?),
+                            }
+                        }
+                    }
+                }
+                clauses = Some(where_clauses);
+            } else {
+                return Err(self.error(key, &[":find", ":in", ":where"]));
+            }
+        }
+
+        let elems = match find {
+            Some(elems) if !elems.is_empty() => elems,
+            _ => return Err(self.error(form, &[":find ?var"])),
+        };
+        let clauses = match clauses {
+            Some(clauses) if !clauses.is_empty() || !predicates.is_empty() ||
+                             !calls.is_empty() || !patterns.is_empty() => clauses,
+            _ => return Err(self.error(form, &[":where [...]"])),
+        };
+
+        let mut query = Query::new(elems.iter().map(|&(ref var, _)| var.clone()).collect(),
+                                   clauses);
+        query.pull = elems
+            .into_iter()
+            .filter_map(|(var, pattern)| pattern.map(|p| (var, p)))
+            .collect();
+        query.inputs = inputs;
+        query.sources = sources;
+        query.predicates = predicates;
+        query.calls = calls;
+        query.patterns = patterns;
+        Ok(query)
+    }
+
+    /// Splits the items of a query vector at each keyword.
+    fn sections<'f>(&self, items: &'f [Form]) -> Result<Vec<(&'f Form, &'f [Form])>> {
+        let mut sections = vec![];
+        let mut rest = items;
+        while let Some((key, tail)) = rest.split_first() {
+            if let Edn::Keyword(_) = key.edn {
+                let len = tail.iter()
+                    .position(|f| match f.edn {
+                                  Edn::Keyword(_) => true,
+                                  _ => false,
+                              })
+                    .unwrap_or(tail.len());
+                sections.push((key, &tail[..len]));
+                rest = &tail[len..];
+            } else {
+                return Err(self.error(key, &[":find"]));
+            }
+        }
+        Ok(sections)
+    }
+
+    fn find_elem(&self, form: &Form) -> Result<(Var, Option<PullPattern>)> {
+        if let Some(var) = as_var(form) {
+            return Ok((var, None));
+        }
+
+        match form.edn {
+            Edn::List(ref items) => {
+                match items[..] {
+                    [ref op, ref var, ref pattern] if op.edn == Edn::Symbol("pull".into()) => {
+                        match as_var(var) {
+                            Some(var) => Ok((var, Some(self.pull_pattern(pattern)?))),
+                            None => Err(self.error(var, &["?var"])),
+                        }
+                    }
+                    _ => Err(self.error(form, &["(pull ?var [...])"])),
+                }
+            }
+            _ => Err(self.error(form, &["?var", "(pull ?var [...])"])),
+        }
+    }
+
+    /// `[$r [?a ?b]]`, a tuple matched against a relation source, if
+    /// that's what `form` is.
+    fn pattern(&mut self, form: &Form) -> Result<Option<TuplePattern>> {
+        let (source, tuple) = match form.edn {
+            Edn::Vector(ref items) if items.len() == 2 => {
+                match (as_source(&items[0]), &items[1].edn) {
+                    (Some(source), &Edn::Vector(ref tuple)) => (source, tuple),
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        if source.is_empty() {
+            return Err(self.error(form, &["[$source [?a ?b]]"]));
+        }
+        let terms = tuple
+            .iter()
+            .map(|item| self.term(item, |c, f| c.value(f)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(TuplePattern::new(source, terms)))
+    }
+
+    /// `[e a v]`, optionally followed by the transaction and whether
+    /// the fact was added, and optionally preceded by a source.
+    fn clause(&mut self, form: &Form) -> Result<Clause> {
+        let (source, items) = match form.edn {
+            Edn::Vector(ref items) => {
+                match items.first().and_then(as_source) {
+                    Some(source) => (Some(source), &items[1..]),
+                    None => (None, &items[..]),
+                }
+            }
+            _ => (None, &[][..]),
+        };
+        if items.len() < 3 || items.len() > 5 {
+            return Err(self.error(form, &["[?e :attr ?v]"]));
+        }
+
+        let e = self.term(&items[0], |c, f| c.entity(f))?;
+        let a = self.term(&items[1], |c, f| c.attribute(f))?;
+        let v = self.term(&items[2], |c, f| c.value(f))?;
+        let mut clause = Clause::new(e, a, v);
+        if let Some(tx) = items.get(3) {
+            clause = clause.with_tx(self.term(tx, |c, f| c.entity(f))?);
+        }
+        if let Some(added) = items.get(4) {
+            clause = clause.with_added(self.term(added, |c, f| c.boolean(f))?);
+        }
+        match source {
+            Some(ref name) if !name.is_empty() => Ok(clause.with_source(name.clone())),
+            _ => Ok(clause),
+        }
+    }
+
+    /// The `(> ?a 30)` of `[(> ?a 30)]`.
+    fn predicate(&mut self, call: &Form) -> Result<Predicate> {
+        let args = match call.edn {
+            Edn::List(ref args) if args.len() == 3 => args,
+            _ => return Err(self.error(call, &["(op ?a ?b)"])),
+        };
+
+        let op = match args[0].edn {
+            Edn::Symbol(ref s) => Comparison::from_symbol(s),
+            _ => None,
+        };
+        let op = op.ok_or_else(|| self.error(&args[0], &["=", "!=", "<", "<=", ">", ">="]))?;
+        Ok(Predicate {
+               op,
+               left: self.term(&args[1], |c, f| c.value(f))?,
+               right: self.term(&args[2], |c, f| c.value(f))?,
+           })
+    }
+
+    /// The `(get-else $ ?e :age 0)` of `[(get-else $ ?e :age 0) ?age]`
+    /// and its binding form. A `$` argument names the database, which
+    /// is the only one functions can read, so it's left out.
+    fn call(&mut self, call: &Form, bind: Option<&Form>) -> Result<Call> {
+        let items = match call.edn {
+            Edn::List(ref items) => items,
+            _ => return Err(self.error(call, &["(function ...)"])),
+        };
+        let function = match items.first().map(|f| &f.edn) {
+            Some(&Edn::Symbol(ref s)) => Function::from_name(s),
+            _ => None,
+        };
+        let function = function
+            .ok_or_else(|| self.error(call, &["a comparison", "a built-in function"]))?;
+
+        let mut args = vec![];
+        for arg in &items[1..] {
+            match as_source(arg) {
+                Some(ref name) if name.is_empty() => {}
+                Some(_) => return Err(self.error(arg, &["$"])),
+                None => args.push(self.term(arg, |c, f| c.list_or_value(f))?),
+            }
+        }
+
+        let mut call = Call::new(function, args);
+        if let Some(bind) = bind {
+            call = call.bind(self.bind_form(bind)?);
+        }
+        Ok(call.with_output_arg())
+    }
+
+    /// `?x`, `[?a ?b]`, `[?x ...]` or `[[?a ?b]]`.
+    fn bind_form(&self, form: &Form) -> Result<BindForm> {
+        let expected = &["?var", "[?a ?b]", "[?x ...]", "[[?a ?b]]"];
+        if let Some(var) = as_var(form) {
+            return Ok(BindForm::Scalar(var));
+        }
+        let vars = |forms: &[Form]| -> Option<Vec<Var>> {
+            let vars = forms.iter().filter_map(as_var).collect::<Vec<_>>();
+            if vars.is_empty() || vars.len() != forms.len() {
+                None
+            } else {
+                Some(vars)
+            }
+        };
+
+        let bind = match form.edn {
+            Edn::Vector(ref items) => {
+                match items[..] {
+                    [ref var, ref dots] if dots.edn == Edn::Symbol("...".into()) => {
+                        as_var(var).map(BindForm::Collection)
+                    }
+                    [Form { edn: Edn::Vector(ref tuple), .. }] => {
+                        vars(tuple).map(BindForm::Relation)
+                    }
+                    _ => vars(items).map(BindForm::Tuple),
+                }
+            }
+            _ => None,
+        };
+        bind.ok_or_else(|| self.error(form, expected))
+    }
+
+    /// A value, or a vector of them as in `(ground [1 2 3])`.
+    fn list_or_value(&self, form: &Form) -> Result<Value> {
+        match form.edn {
+            Edn::Vector(ref items) => {
+                items
+                    .iter()
+                    .map(|item| self.list_or_value(item))
+                    .collect::<Result<_>>()
+                    .map(Value::List)
+            }
+            _ => self.value(form),
+        }
+    }
+
+    fn term<T, F>(&mut self, form: &Form, bound: F) -> Result<Term<T>>
+        where F: FnOnce(&Self, &Form) -> Result<T>
+    {
+        if form.edn == Edn::Symbol("_".into()) {
+            self.blanks += 1;
+            return Ok(Term::Unbound(Var::new(format!("_{}", self.blanks))));
+        }
+
+        match as_var(form) {
+            Some(var) => Ok(Term::Unbound(var)),
+            None => bound(self, form).map(Term::Bound),
+        }
+    }
+
+    fn entity(&self, form: &Form) -> Result<Entity> {
+        match form.edn {
+            Edn::Integer(n) if n >= 0 => Ok(Entity(n as u64)),
+            _ => Err(self.error(form, &["an entity id"])),
+        }
+    }
+
+    fn attribute(&self, form: &Form) -> Result<String> {
+        match form.edn {
+            Edn::Keyword(ref k) => Ok(ident_name(k)),
+            _ => Err(self.error(form, &[":attribute"])),
+        }
+    }
+
+    fn boolean(&self, form: &Form) -> Result<Value> {
+        match form.edn {
+            Edn::Boolean(b) => Ok(Value::Boolean(b)),
+            _ => Err(self.error(form, &["true", "false"])),
+        }
+    }
+
+    fn value(&self, form: &Form) -> Result<Value> {
+        match form.edn {
+            Edn::String(ref s) => Ok(Value::String(s.clone())),
+            Edn::Keyword(ref k) => Ok(Value::Ident(ident_name(k))),
+            Edn::Integer(n) => Ok(Value::Integer(n)),
+            Edn::Float(x) => Ok(Value::Float(Float(x))),
+            Edn::Boolean(b) => Ok(Value::Boolean(b)),
+            _ => Err(self.error(form, &["a value"])),
+        }
+    }
+
+    /// `[* :name {:parent [:name]} :_parent (limit :child 2)]`.
+    /// Datomic's `:ns/_attr` is accepted for reverse attributes too.
+    fn pull_pattern(&self, form: &Form) -> Result<PullPattern> {
+        let items = match form.edn {
+            Edn::Vector(ref items) => items,
+            _ => return Err(self.error(form, &["[...]"])),
+        };
+
+        let mut attrs = vec![];
+        for item in items {
+            match item.edn {
+                Edn::Symbol(ref s) if s == "*" => attrs.push(PullAttr::Wildcard),
+                Edn::Keyword(_) |
+                Edn::List(_) => attrs.push(PullAttr::Attr(self.attr_expr(item)?)),
+                Edn::Map(ref entries) => {
+                    for &(ref key, ref pattern) in entries {
+                        let spec = AttrSpec {
+                            pattern: Some(self.pull_pattern(pattern)?),
+                            ..self.attr_expr(key)?
+                        };
+                        attrs.push(PullAttr::Attr(spec));
+                    }
+                }
+                _ => return Err(self.error(item, &["*", ":attribute", "{:attribute [...]}"])),
+            }
+        }
+        Ok(PullPattern(attrs))
+    }
+
+    fn attr_expr(&self, form: &Form) -> Result<AttrSpec> {
+        match form.edn {
+            Edn::Keyword(ref k) => Ok(attr_spec(k)),
+            Edn::List(ref items) => {
+                match items[..] {
+                    [ref op, ref attr, ref n] if op.edn == Edn::Symbol("limit".into()) => {
+                        let spec = self.attr_expr(attr)?;
+                        match n.edn {
+                            Edn::Integer(n) if n >= 0 => {
+                                Ok(AttrSpec {
+                                       limit: Some(n as usize),
+                                       ..spec
+                                   })
+                            }
+                            _ => Err(self.error(n, &["a limit"])),
+                        }
+                    }
+                    [ref op, ref attr, ref v] if op.edn == Edn::Symbol("default".into()) => {
+                        Ok(AttrSpec {
+                               default: Some(self.value(v)?),
+                               ..self.attr_expr(attr)?
+                           })
+                    }
+                    _ => Err(self.error(form, &["(limit :attribute n)", "(default :attribute v)"])),
+                }
+            }
+            _ => Err(self.error(form, &[":attribute"])),
+        }
+    }
+
+    /// A vector of `[:db/add e a v]`, `[:db/retract e a v]` and maps.
+    /// A map with a `:db/id` adds its attributes to that entity;
+    /// otherwise it creates a new one.
+    fn tx(&self, form: &Form) -> Result<Tx> {
+        let forms = match form.edn {
+            Edn::Vector(ref forms) if !forms.is_empty() => forms,
+            _ => return Err(self.error(form, &["[[:db/add ...] ...]"])),
+        };
+
+        let mut items = vec![];
+        for item in forms {
+            match item.edn {
+                Edn::Vector(ref parts) if parts.len() == 4 => {
+                    let fact = Fact::new(self.entity(&parts[1])?,
+                                         self.attribute(&parts[2])?,
+                                         self.value(&parts[3])?);
+                    if is_keyword(&parts[0], "db/add") {
+                        items.push(TxItem::Addition(fact));
+                    } else if is_keyword(&parts[0], "db/retract") {
+                        items.push(TxItem::Retraction(fact));
+                    } else {
+                        return Err(self.error(&parts[0], &[":db/add", ":db/retract"]));
+                    }
+                }
+                Edn::Map(ref entries) => {
+                    let id = entries.iter().find(|&&(ref k, _)| is_keyword(k, "db/id"));
+                    let mut attrs = HashMap::new();
+                    for &(ref k, ref v) in entries {
+                        if !is_keyword(k, "db/id") {
+                            attrs.insert(self.attribute(k)?, self.value(v)?);
+                        }
+                    }
+
+                    match id {
+                        Some(&(_, ref id)) => {
+                            let entity = self.entity(id)?;
+                            items.extend(attrs
+                                             .into_iter()
+                                             .map(|(a, v)| TxItem::Addition(Fact::new(entity, a, v))));
+                        }
+                        None => items.push(TxItem::NewEntity(attrs)),
+                    }
+                }
+                _ => return Err(self.error(item, &["[:db/add e :attribute v]", "{:attribute v}"])),
+            }
+        }
+        Ok(Tx { items })
+    }
+}
+
+fn as_var(form: &Form) -> Option<Var> {
+    match form.edn {
+        Edn::Symbol(ref s) if s.len() > 1 && s.starts_with('?') => Some(Var::new(&s[1..])),
+        _ => None,
+    }
+}
+
+/// The name of a source such as `$old`, which is empty for `$`.
+fn as_source(form: &Form) -> Option<String> {
+    match form.edn {
+        Edn::Symbol(ref s) if s.starts_with('$') => Some(s[1..].to_string()),
+        _ => None,
+    }
+}
+
+/// The ident a keyword names. The system idents are written
+/// `:db/ident`, `:db/txInstant` and so on, as in Datomic, but named
+/// `db:ident` and so on.
+fn ident_name(keyword: &str) -> String {
+    if keyword.starts_with("db/") {
+        format!("db:{}", &keyword[3..])
+    } else {
+        keyword.to_string()
+    }
+}
+
+/// `:_parent` and `:person/_parent` are both the reverse of the
+/// attribute without the underscore.
+fn attr_spec(keyword: &str) -> AttrSpec {
+    let (ns, name) = match keyword.rfind('/') {
+        Some(i) => keyword.split_at(i + 1),
+        None => ("", keyword),
+    };
+
+    if name.len() > 1 && name.starts_with('_') {
+        AttrSpec {
+            reverse: true,
+            ..AttrSpec::new(ident_name(&format!("{}{}", ns, &name[1..])))
+        }
+    } else {
+        AttrSpec::new(ident_name(keyword))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse_query as parse_text_query, parse_tx as parse_text_tx};
+
+    #[test]
+    fn test_edn_query() {
+        let edn = parse_query(r#"[:find ?a ?b
+                                  :where [?a :name "Bob"], [?a :parent ?b]]"#)
+                .unwrap();
+        let text = parse_text_query(r#"find ?a ?b where (?a name "Bob") (?a parent ?b)"#).unwrap();
+        assert_eq!(edn, text);
+
+        let map = parse_query(r#"{:find [?a ?b] :where [[?a :name "Bob"] [?a :parent ?b]]}"#)
+            .unwrap();
+        assert_eq!(map, text);
+    }
+
+    #[test]
+    fn test_edn_inputs() {
+        let edn = parse_query(r#"[:find ?b :in $ ?name :where [?a :name ?name] [?a :parent ?b]]"#)
+            .unwrap();
+        let text = parse_text_query("find ?b in ?name where (?a name ?name) (?a parent ?b)").unwrap();
+        assert_eq!(edn.inputs, vec![Var::new("name")]);
+        assert_eq!(edn, text);
+    }
+
+    #[test]
+    fn test_edn_sources() {
+        let edn = parse_query(r#"[:find ?n :in $ $old :where [?e :name ?n] [$old ?e :name ?n]]"#)
+            .unwrap();
+        let text = parse_text_query("find ?n in $old where (?e name ?n) $old (?e name ?n)")
+            .unwrap();
+        assert_eq!(edn, text);
+        assert!(parse_query(r#"[:find ?n :where [$old ?e :name]]"#).is_err());
+
+        let edn = parse_query(r#"[:find ?n :in $r :where [$r [?n 30]] [?e :name ?n]]"#).unwrap();
+        let text = parse_text_query("find ?n in $r where $r [?n 30] (?e name ?n)").unwrap();
+        assert_eq!(edn, text);
+        assert!(parse_query(r#"[:find ?n :where [$ [?n]]]"#).is_err());
+    }
+
+    #[test]
+    fn test_edn_predicates() {
+        let edn = parse_query(r#"[:find ?e :where [?e :age ?a] [(>= ?a 30)] [(< ?a 40.5)]]"#)
+            .unwrap();
+        let text = parse_text_query("find ?e where (?e age ?a) [(>= ?a 30)] [(< ?a 40.5)]")
+            .unwrap();
+        assert_eq!(edn, text);
+        assert_eq!(edn.predicates.len(), 2);
+
+        assert!(parse_query(r#"[:find ?e :where [?e :age ?a] [(> ?a)]]"#).is_err());
+        assert!(parse_query(r#"[:find ?e :where [?e :age ?a] [(older ?a 30)]]"#).is_err());
+    }
+
+    #[test]
+    fn test_edn_calls() {
+        let edn = parse_query(r#"[:find ?e ?a :where [?e :name ?n] [(get-else $ ?e :age 0) ?a]
+                                  [(missing? $ ?e :parent)] [(ground [1 2]) [?x ...]]
+                                  [(untuple ?t) [?b ?c]] [(ground [[1 "a"]]) [[?i ?s]]]]"#)
+                .unwrap();
+        let text = parse_text_query(r#"find ?e ?a where (?e name ?n) [(get-else ?e age 0) ?a]
+                                       [(missing? ?e parent)] [(ground [1 2]) [?x ...]]
+                                       [(untuple ?t) [?b ?c]] [(ground [[1 "a"]]) [[?i ?s]]]"#)
+                .unwrap();
+        assert_eq!(edn, text);
+
+        let edn = parse_query(r#"[:find ?to :where [(reachable $ ?e :_parent 2 ?to)]]"#).unwrap();
+        let text = parse_text_query("find ?to where [(reachable ?e _parent 2) [?to ...]]")
+            .unwrap();
+        assert_eq!(edn, text);
+
+        assert!(parse_query(r#"[:find ?a :where [(get-else $old ?e :age 0) ?a]]"#).is_err());
+        assert!(parse_query(r#"[:find ?a :where [(ground [1 2]) [?a ?b ...]]]"#).is_err());
+    }
+
+    #[test]
+    fn test_edn_history_and_pull() {
+        let edn = parse_query(r#"[:find ?n (pull ?e [* {:parent [:name]} (limit :person/_parent 2)])
+                                  :where [?e :name ?n ?tx false]]"#)
+                .unwrap();
+        assert_eq!(edn.clauses,
+                   vec![Clause::new(Term::Unbound("e".into()),
+                                    Term::Bound("name".into()),
+                                    Term::Unbound("n".into()))
+                            .with_tx(Term::Unbound("tx".into()))
+                            .with_added(Term::Bound(Value::Boolean(false)))]);
+        assert_eq!(edn.pull[&Var::new("e")],
+                   PullPattern(vec![PullAttr::Wildcard,
+                                    PullAttr::Attr(AttrSpec {
+                                                       pattern: Some(PullPattern(vec![
+                        PullAttr::Attr(AttrSpec::new("name")),
+                    ])),
+                                                       ..AttrSpec::new("parent")
+                                                   }),
+                                    PullAttr::Attr(AttrSpec {
+                                                       reverse: true,
+                                                       limit: Some(2),
+                                                       ..AttrSpec::new("person/parent")
+                                                   })]));
+    }
+
+    #[test]
+    fn test_edn_tx() {
+        let edn = parse_tx(r#"[[:db/add 0 :name "Bob"]
+                               [:db/retract 1 :parent 0]
+                               {:db/id 2 :age -3}]"#)
+                .unwrap();
+        let text = parse_text_tx(r#"add (0 name "Bob") retract (1 parent 0) add (2 age -3)"#)
+            .unwrap();
+        assert_eq!(edn, text);
+
+        match parse_tx(r#"[{:name "Jane"}]"#).unwrap().items[..] {
+            [TxItem::NewEntity(ref attrs)] => {
+                assert_eq!(attrs["name"], Value::String("Jane".into()))
+            }
+            ref other => panic!("expected a new entity, got {:?}", other),
+        }
+
+        // The system idents are in the `db` namespace.
+        let edn = parse_tx(r#"[{:db/ident :name :db/fulltext true} [:db/add 3 :db/ident :age]]"#)
+            .unwrap();
+        let text = parse_text_tx("{db:ident name db:fulltext true} add (3 db:ident age)").unwrap();
+        assert_eq!(edn, text);
+        let edn = parse_query("[:find (pull ?tx [:db/txInstant]) :where [?e :name ?n ?tx]]")
+            .unwrap();
+        let text = parse_text_query("find (pull ?tx [db:txInstant]) where (?e name ?n ?tx)")
+            .unwrap();
+        assert_eq!(edn.pull, text.pull);
+    }
+
+    #[test]
+    fn test_edn_errors() {
+        let err = parse_query("[:find ?a\n :wehre [?a :name \"Bob\"]]").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 2));
+        assert_eq!(err.suggestion, Some(":where".to_string()));
+
+        let err = parse_tx("[[:db/add 0 :name]]").unwrap_err();
+        assert_eq!(err.unexpected, Some("[:db/add 0 :name]".to_string()));
+    }
+
+    #[test]
+    fn test_detection() {
+        assert!(is_edn("  ; comment\n [:find ?a]"));
+        assert!(is_edn("{:find [?a]}"));
+        assert!(!is_edn("{name \"Bob\"}"));
+        assert!(!is_edn("find ?a where (?a name \"Bob\")"));
+
+        match parse_input(r#"[:find ?a :where [?a :name "Bob"]]"#) {
+            Ok(Input::Query(_)) => {}
+            _ => panic!("expected a query"),
+        }
+        match parse_input(r#"[[:db/add 0 :name "Bob"]]"#) {
+            Ok(Input::Tx(_)) => {}
+            _ => panic!("expected a transaction"),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+use combine;
+use combine::primitives::{Error as CombineError, State};
+
+use Error;
+
+/// Describes where and why some input couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line of the error.
+    pub line: usize,
+    /// 1-based column of the error, in characters.
+    pub column: usize,
+    /// The line of input the error is on.
+    pub snippet: String,
+    pub unexpected: Option<String>,
+    pub expected: Vec<String>,
+    /// A keyword that the input at the error looks like a misspelling
+    /// of, e.g. `find` for `fnid`.
+    pub suggestion: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(input: &str, err: combine::ParseError<State<&str>>) -> ParseError {
+        let line = err.position.line as usize;
+        let column = err.position.column as usize;
+
+        let mut unexpected = None;
+        let mut expected: Vec<String> = vec![];
+        let mut messages = vec![];
+        for e in err.errors {
+            match e {
+                CombineError::Unexpected(info) => {
+                    if unexpected.is_none() {
+                        unexpected = Some(info.to_string());
+                    }
+                }
+                CombineError::Expected(info) => {
+                    let info = info.to_string();
+                    if !expected.contains(&info) {
+                        expected.push(info);
+                    }
+                }
+                CombineError::Message(info) => messages.push(info.to_string()),
+                CombineError::Other(err) => messages.push(err.to_string()),
+            }
+        }
+        if unexpected.is_none() && !messages.is_empty() {
+            unexpected = Some(messages.join(", "));
+        }
+
+        ParseError::at(input, line, column, unexpected, expected)
+    }
+
+    /// An error at the given position in `input`, for problems found
+    /// after the input has been read.
+    pub fn at(input: &str,
+              line: usize,
+              column: usize,
+              unexpected: Option<String>,
+              expected: Vec<String>)
+              -> ParseError {
+        let snippet = input.lines().nth(line - 1).unwrap_or("").to_string();
+        let suggestion = suggest(&word_at(&snippet, column), &expected);
+
+        ParseError {
+            line,
+            column,
+            snippet,
+            unexpected,
+            expected,
+            suggestion,
+        }
+    }
+
+    /// Everything but the position: what went wrong, the offending
+    /// line with a caret under the error, and any suggestion.
+    pub fn message(&self) -> String {
+        let mut msg = match self.unexpected {
+            Some(ref u) if u == "end of input" => "unexpected end of input".to_string(),
+            Some(ref u) => format!("unexpected `{}`", u),
+            None => "invalid input".to_string(),
+        };
+
+        if !self.expected.is_empty() {
+            let expected = self.expected
+                .iter()
+                .map(|e| format!("`{}`", e))
+                .collect::<Vec<_>>();
+            let (last, rest) = expected.split_last().unwrap();
+            if rest.is_empty() {
+                msg.push_str(&format!("; expected {}", last));
+            } else {
+                msg.push_str(&format!("; expected {} or {}", rest.join(", "), last));
+            }
+        }
+
+        msg.push_str(&format!("\n    {}\n    {}^",
+                              self.snippet,
+                              " ".repeat(self.column.saturating_sub(1))));
+
+        if let Some(ref suggestion) = self.suggestion {
+            msg.push_str(&format!("\ndid you mean `{}`?", suggestion));
+        }
+
+        msg
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f,
+               "parse error at line {}, column {}: {}",
+               self.line,
+               self.column,
+               self.message())
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "parse error"
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse {
+            line: err.line,
+            column: err.column,
+            message: err.message(),
+        }
+    }
+}
+
+/// The word around the given 1-based column; the error may be
+/// reported partway through it.
+fn word_at(line: &str, column: usize) -> String {
+    let chars = line.chars().collect::<Vec<_>>();
+    let is_word = |c: &char| c.is_alphanumeric() || ":_-/".contains(*c);
+    let pos = column.saturating_sub(1).min(chars.len());
+
+    let start = chars[..pos]
+        .iter()
+        .rposition(|c| !is_word(c))
+        .map_or(0, |i| i + 1);
+    let end = chars[pos..]
+        .iter()
+        .position(|c| !is_word(c))
+        .map_or(chars.len(), |i| pos + i);
+
+    chars[start..end].iter().collect()
+}
+
+/// The expected keyword closest to `word`, if it's close enough to be
+/// a plausible typo.
+fn suggest(word: &str, expected: &[String]) -> Option<String> {
+    if word.is_empty() {
+        return None;
+    }
+
+    expected
+        .iter()
+        .filter(|e| e.len() > 1 && e.chars().all(|c| c.is_alphabetic() || ":/".contains(c)))
+        .filter(|e| e.as_str() != word)
+        .map(|e| (edit_distance(word, e), e))
+        .filter(|&(d, e)| d <= e.len() / 2)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, e)| e.clone())
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..b.len() + 1).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            let best = (prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1);
+            row.push(best);
+        }
+        prev = row;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse_input, parse_query, parse_tx};
+
+    #[test]
+    fn test_error_position() {
+        let err = parse_query("find ?a\nwhere (?a name \"Bob\"").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 21));
+        assert_eq!(err.snippet, "where (?a name \"Bob\"");
+        assert_eq!(err.unexpected, Some("end of input".to_string()));
+    }
+
+    #[test]
+    fn test_expected_tokens() {
+        let err = parse_tx("add 0 name \"Bob\"").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 5));
+        assert_eq!(err.expected, vec!["("]);
+        assert_eq!(err.message(),
+                   "unexpected `0`; expected `(`\n    add 0 name \"Bob\"\n        ^");
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let err = parse_input("fnid ?a where (?a name \"Bob\")").err().unwrap();
+        assert_eq!(err.suggestion, Some("find".to_string()));
+        assert!(err.to_string().ends_with("did you mean `find`?"));
+
+        let err = parse_query("find ?a wehre (?a name \"Bob\")").unwrap_err();
+        assert_eq!(err.suggestion, Some("where".to_string()));
+    }
+
+    #[test]
+    fn test_into_error() {
+        match Error::from(parse_query("find").unwrap_err()) {
+            Error::Parse { line: 1, column: 5, .. } => {}
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("find", "find"), 0);
+        assert_eq!(edit_distance("fnid", "find"), 2);
+        assert_eq!(edit_distance("ad", "add"), 1);
+        assert_eq!(edit_distance("", "dump"), 4);
+    }
+}
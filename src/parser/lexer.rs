@@ -0,0 +1,220 @@
+//! Tokens shared by every syntax. Each token parser also skips any
+//! whitespace and comments after it.
+
+use std::char;
+
+use combine::char::{char, digit, hex_digit, letter, space, string};
+use combine::primitives::{Error, Stream};
+use combine::{Parser, any, between, many, many1, none_of, not_followed_by, optional,
+              satisfy, skip_many, try};
+
+use {Entity, Float, Value};
+use query::{Function, Var};
+
+/// Skips whitespace and `;` line comments.
+pub fn whitespace<I>() -> impl Parser<Input = I, Output = ()>
+    where I: Stream<Item = char>
+{
+    let comment = char(';').with(skip_many(satisfy(|c| c != '\n')));
+    skip_many(space().map(|_| ()).or(comment))
+}
+
+pub fn lex_char<I>(c: char) -> impl Parser<Input = I>
+    where I: Stream<Item = char>
+{
+    char(c).skip(whitespace())
+}
+
+/// Punctuation of more than one character, such as `:-`.
+pub fn symbol<I>(s: &'static str) -> impl Parser<Input = I>
+    where I: Stream<Item = char>
+{
+    try(string(s)).skip(whitespace())
+}
+
+/// A reserved word, which mustn't run on into a longer ident: `where`
+/// matches `where (` but not `wherever`.
+pub fn keyword<I>(s: &'static str) -> impl Parser<Input = I>
+    where I: Stream<Item = char>
+{
+    try(string(s).skip(not_followed_by(ident_char()))).skip(whitespace())
+}
+
+fn ident_char<I>() -> impl Parser<Input = I, Output = char>
+    where I: Stream<Item = char>
+{
+    satisfy(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '/' || c == ':')
+}
+
+/// An attribute or ident such as `name`, `db:ident` or
+/// `person/first-name`.
+pub fn ident<I>() -> impl Parser<Input = I, Output = String>
+    where I: Stream<Item = char>
+{
+    letter()
+        .or(char(':'))
+        .and(many(ident_char()))
+        .map(|(c, rest): (char, String)| format!("{}{}", c, rest))
+        .skip(whitespace())
+}
+
+/// The name of a built-in function, such as `get-else` or `missing?`.
+pub fn function<I>() -> impl Parser<Input = I, Output = Function>
+    where I: Stream<Item = char>
+{
+    letter()
+        .and(many(ident_char().or(char('?'))))
+        .and_then(|(c, rest): (char, String)| {
+                      let name = format!("{}{}", c, rest);
+                      Function::from_name(&name)
+                          .ok_or(Error::Message(format!("unknown function {}", name).into()))
+                  })
+        .skip(whitespace())
+}
+
+/// A variable such as `?a`, `?person2` or `?first_name`.
+pub fn free_var<I>() -> impl Parser<Input = I, Output = Var>
+    where I: Stream<Item = char>
+{
+    char('?')
+        .with(many1(ident_char()))
+        .map(|name: String| Var::new(name))
+        .skip(whitespace())
+}
+
+/// A data source such as `$old`, without its `$`. A lone `$` is the
+/// database being queried, and is named by the empty string.
+pub fn source_var<I>() -> impl Parser<Input = I, Output = String>
+    where I: Stream<Item = char>
+{
+    char('$').with(many(ident_char())).skip(whitespace())
+}
+
+/// A non-negative integer naming an entity.
+pub fn entity_lit<I>() -> impl Parser<Input = I, Output = Entity>
+    where I: Stream<Item = char>
+{
+    many1(digit())
+        .and_then(|n: String| {
+                      n.parse()
+                          .map(Entity)
+                          .map_err(|_| Error::Message("entity id out of range".into()))
+                  })
+        .skip(whitespace())
+}
+
+/// A non-negative integer used as a count, e.g. a pull limit.
+pub fn count_lit<I>() -> impl Parser<Input = I, Output = usize>
+    where I: Stream<Item = char>
+{
+    many1(digit())
+        .and_then(|n: String| {
+                      n.parse().map_err(|_| Error::Message("count out of range".into()))
+                  })
+        .skip(whitespace())
+}
+
+/// A number in a value position: a `Value::Integer`, or a
+/// `Value::Float` if it has a decimal point. Integers are only taken
+/// as entities where an entity is expected.
+pub fn number_lit<I>() -> impl Parser<Input = I, Output = Value>
+    where I: Stream<Item = char>
+{
+    (optional(char('-')), many1(digit()), optional(char('.').with(many1(digit()))))
+        .and_then(|(sign, int, frac): (Option<char>, String, Option<String>)| {
+            let sign = if sign.is_some() { "-" } else { "" };
+            let value = match frac {
+                Some(frac) => {
+                    format!("{}{}.{}", sign, int, frac).parse().ok().map(|f| Value::Float(Float(f)))
+                }
+                None => format!("{}{}", sign, int).parse().ok().map(Value::Integer),
+            };
+            value.ok_or(Error::Message("number out of range".into()))
+        })
+        .skip(whitespace())
+}
+
+/// A double-quoted string, which may be empty and may contain the
+/// escapes `\"`, `\\`, `\n`, `\r`, `\t` and `\u{...}`.
+pub fn string_lit<I>() -> impl Parser<Input = I, Output = String>
+    where I: Stream<Item = char>
+{
+    let unicode = between(char('{'), char('}'), many1(hex_digit())).and_then(|hex: String| {
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(Error::Message("invalid unicode escape".into()))
+    });
+    let simple = any().and_then(|c| match c {
+                                    '"' => Ok('"'),
+                                    '\\' => Ok('\\'),
+                                    'n' => Ok('\n'),
+                                    'r' => Ok('\r'),
+                                    't' => Ok('\t'),
+                                    _ => Err(Error::Message(format!("unknown escape \\{}", c).into())),
+                                });
+    let escape = char('\\').with(char('u').with(unicode).or(simple));
+
+    between(char('"'), char('"'), many(escape.or(none_of(vec!['"', '\\']))))
+        .skip(whitespace())
+}
+
+pub fn bool_lit<I>() -> impl Parser<Input = I, Output = Value>
+    where I: Stream<Item = char>
+{
+    keyword("true")
+        .map(|_| Value::Boolean(true))
+        .or(keyword("false").map(|_| Value::Boolean(false)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{parse_query, parse_tx};
+    use {Fact, Tx, TxItem};
+
+    fn lex<P: Parser<Input = &'static str>>(mut p: P, input: &'static str) -> P::Output {
+        p.parse(input).unwrap().0
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(lex(string_lit(), r#""""#), "");
+        assert_eq!(lex(string_lit(), r#""say \"hi\"\\\n""#), "say \"hi\"\\\n");
+        assert_eq!(lex(string_lit(), r#""caf\u{e9} ☕""#), "café ☕");
+        assert!(string_lit().parse(r#""\q""#).is_err());
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(lex(number_lit(), "42"), Value::Integer(42));
+        assert_eq!(lex(number_lit(), "-42"), Value::Integer(-42));
+        assert_eq!(lex(number_lit(), "-0.5"), Value::Float(Float(-0.5)));
+        assert!(number_lit().parse("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(lex(ident(), "person/first-name"), "person/first-name");
+        assert_eq!(lex(free_var(), "?first_name2"), Var::new("first_name2"));
+        assert_eq!(lex(source_var(), "$old"), "old");
+        assert_eq!(lex(source_var(), "$ "), "");
+        assert!(keyword("where").parse("wherever").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let q = parse_query("; everyone called Bob\n\
+                             find ?person2 ; the entity\n\
+                             where (?person2 person/first-name \"Bob\")")
+                .unwrap();
+        assert_eq!(q.find, vec![Var::new("person2")]);
+
+        assert_eq!(parse_tx("add (0 balance -12.5) ; overdrawn").unwrap(),
+                   Tx {
+                       items: vec![TxItem::Addition(Fact::new(Entity(0),
+                                                              "balance",
+                                                              Value::Float(Float(-12.5))))],
+                   });
+    }
+}
@@ -0,0 +1,599 @@
+use super::*;
+
+//// Parser
+use combine::char::char;
+use combine::primitives::{Stream, ParseResult, State};
+use combine::{Parser, many, many1, between, eof, optional, parser, try};
+use pull::{PullPattern, PullAttr, AttrSpec};
+
+mod datalog;
+mod edn;
+mod error;
+mod lexer;
+
+pub use self::datalog::{Program, parse_program};
+pub use self::error::ParseError;
+use self::lexer::*;
+
+pub enum Input {
+    Query(Query),
+    Tx(Tx),
+    Program(Program),
+    /// `explain find ...`: show how the query would run.
+    Explain(Query),
+    /// `profile find ...`: run the query, showing what each step took.
+    Profile(Query),
+    SampleDb,
+    Dump,
+}
+
+// Each of these accepts either our own syntax or EDN, as in
+// `[:find ?a :where [?a :name "Bob"]]`. `parse_input` also accepts
+// Datalog programs.
+
+pub fn parse_input(input: &str) -> result::Result<Input, ParseError> {
+    if edn::is_edn(input) {
+        return edn::parse_input(input);
+    }
+    if datalog::is_datalog(input) {
+        return parse_program(input).map(Input::Program);
+    }
+
+    run(choice!(query_parser().map(Input::Query),
+                tx_parser().map(Input::Tx),
+                explain_parser(),
+                sample_db_parser(),
+                dump_parser()),
+        input)
+}
+
+pub fn parse_query(input: &str) -> result::Result<Query, ParseError> {
+    if edn::is_edn(input) {
+        return edn::parse_query(input);
+    }
+
+    run(query_parser(), input)
+}
+
+pub fn parse_tx(input: &str) -> result::Result<Tx, ParseError> {
+    if edn::is_edn(input) {
+        return edn::parse_tx(input);
+    }
+
+    run(tx_parser(), input)
+}
+
+/// Parses a pull pattern such as `[name {parent [name]} _parent]`.
+pub fn parse_pull_pattern(input: &str) -> result::Result<PullPattern, ParseError> {
+    run(pull_pattern().skip(eof()), input)
+}
+
+/// The input after any leading whitespace and comments.
+fn start_of(input: &str) -> &str {
+    let mut rest = input.trim_left();
+    while rest.starts_with(';') {
+        rest = rest.find('\n').map_or("", |i| &rest[i..]).trim_left();
+    }
+    rest
+}
+
+/// Runs `parser` over the whole of `input`, tracking line and column
+/// so they can be reported in errors.
+fn run<'a, P>(parser: P, input: &'a str) -> result::Result<P::Output, ParseError>
+    where P: Parser<Input = State<&'a str>>
+{
+    whitespace()
+        .with(parser)
+        .parse(State::new(input))
+        .map(|(r, _)| r)
+        .map_err(|err| ParseError::new(input, err))
+}
+
+fn sample_db_parser<I>() -> impl Parser<Input = I, Output = Input>
+    where I: combine::Stream<Item = char>
+{
+    keyword("test").and(eof()).map(|_| Input::SampleDb)
+}
+
+fn explain_parser<I>() -> impl Parser<Input = I, Output = Input>
+    where I: combine::Stream<Item = char>
+{
+    keyword("explain")
+        .with(query_parser())
+        .map(Input::Explain)
+        .or(keyword("profile").with(query_parser()).map(Input::Profile))
+}
+
+fn dump_parser<I>() -> impl Parser<Input = I, Output = Input>
+    where I: combine::Stream<Item = char>
+{
+    keyword("dump").and(eof()).map(|_| Input::Dump)
+}
+
+/// A variable or a value.
+fn value_term<I: combine::Stream<Item = char>>() -> impl Parser<Input = I, Output = Term<Value>> {
+    free_var().map(|x| Term::Unbound(x)).or(value().map(|x| Term::Bound(x)))
+}
+
+/// Something in a query's `in` section.
+enum InItem {
+    Input(Var),
+    Source(String),
+}
+
+/// Something in a query's `where` section.
+enum WhereItem {
+    Clause(Clause),
+    Pattern(TuplePattern),
+    Predicate(Predicate),
+    Call(Call),
+}
+
+/// A list of values, as in `[1 2 3]` or `[[1 "a"] [2 "b"]]`.
+fn list_lit<I: combine::Stream<Item = char>>() -> impl Parser<Input = I, Output = Value> {
+    let tuple = between(lex_char('['), lex_char(']'), many(value())).map(Value::List);
+    between(lex_char('['), lex_char(']'), many(value().or(tuple))).map(Value::List)
+}
+
+/// How a call's result is bound: `?x`, `[?a ?b]`, `[?x ...]` or
+/// `[[?a ?b]]`.
+fn bind_form<I: combine::Stream<Item = char>>() -> impl Parser<Input = I, Output = BindForm> {
+    let relation = between(lex_char('['), lex_char(']'), many1(free_var()))
+        .map(BindForm::Relation);
+    let tuple = (many1::<Vec<_>, _>(free_var()), optional(symbol("...")))
+        .and_then(|(mut vars, dots)| match dots {
+                      None => Ok(BindForm::Tuple(vars)),
+                      Some(_) if vars.len() == 1 => Ok(BindForm::Collection(vars.remove(0))),
+                      Some(_) => {
+                          let message = "only one variable can come before ...";
+                          Err(combine::primitives::Error::Message(message.into()))
+                      }
+                  });
+    free_var()
+        .map(BindForm::Scalar)
+        .or(between(lex_char('['), lex_char(']'), relation.or(tuple)))
+}
+
+/// Any literal that can appear as the value of a fact.
+fn value<I: combine::Stream<Item = char>>() -> impl Parser<Input = I, Output = Value> {
+    string_lit()
+        .map(Value::String)
+        .or(number_lit())
+        .or(bool_lit())
+        .or(ident().map(|i| Value::Ident(i)))
+}
+
+fn query_parser<I>() -> impl Parser<Input = I, Output = Query>
+    where I: combine::Stream<Item = char>
+{
+    // Numbers are entities in entity positions and integers in value
+    // positions, where they only become entities when they're matched
+    // against refs.
+
+    // There is probably a way to DRY these out but I couldn't satisfy the type checker.
+    let entity_term = free_var()
+        .map(|x| Term::Unbound(x))
+        .or(entity_lit().map(|x| Term::Bound(x)));
+    let ident_term = free_var()
+        .map(|x| Term::Unbound(x))
+        .or(ident().map(|x| Term::Bound(x)));
+    let tx_term = free_var()
+        .map(|x| Term::Unbound(x))
+        .or(entity_lit().map(|x| Term::Bound(x)));
+    let added_term = free_var()
+        .map(|x| Term::Unbound(x))
+        .or(bool_lit().map(|x| Term::Bound(x)));
+
+    // Clause structure
+    let clause_contents = (entity_term,
+                           ident_term,
+                           value_term(),
+                           optional((tx_term, optional(added_term))));
+    let clause = (optional(source_var()), between(lex_char('('), lex_char(')'), clause_contents))
+        .map(|(source, (e, a, v, tx))| {
+            let clause = match tx {
+                Some((tx, Some(added))) => Clause::new(e, a, v).with_tx(tx).with_added(added),
+                Some((tx, None)) => Clause::new(e, a, v).with_tx(tx),
+                None => Clause::new(e, a, v),
+            };
+            match source {
+                Some(ref name) if !name.is_empty() => clause.with_source(name.clone()),
+                _ => clause,
+            }
+        });
+    // `[(> ?a 30)]`. The longer symbols have to be tried first.
+    let comparison = choice!(symbol("<=").map(|_| Comparison::Le),
+                             symbol(">=").map(|_| Comparison::Ge),
+                             symbol("!=").map(|_| Comparison::Ne),
+                             lex_char('<').map(|_| Comparison::Lt),
+                             lex_char('>').map(|_| Comparison::Gt),
+                             lex_char('=').map(|_| Comparison::Eq));
+    let predicate = (comparison, value_term(), value_term())
+        .skip(lex_char(')'))
+        .map(|(op, left, right)| WhereItem::Predicate(Predicate { op, left, right }));
+    // `[(get-else ?e age 0) ?age]`. Traversals can follow `_attr`
+    // backwards, as pull patterns do.
+    let reverse_attr = char('_').with(ident()).map(|a| Value::Ident(format!("_{}", a)));
+    // `$` can be passed too, as in `[(fulltext $ bio "bob")]`, but it
+    // is always the database being queried, so it isn't an argument.
+    let call_source = source_var().and_then(|name| {
+        if name.is_empty() {
+            Ok(None)
+        } else {
+            let message = "only $ can be passed to a function";
+            Err(combine::primitives::Error::Message(message.into()))
+        }
+    });
+    let call_arg = value_term()
+        .or(list_lit().or(reverse_attr).map(Term::Bound))
+        .map(Some)
+        .or(call_source);
+    let call = (function(), many::<Vec<_>, _>(call_arg))
+        .skip(lex_char(')'))
+        .and(optional(bind_form()))
+        .map(|((function, args), bind)| {
+                 let args = args.into_iter().filter_map(|arg| arg).collect();
+                 WhereItem::Call(Call { function, args, bind }.with_output_arg())
+             });
+    let bracketed = between(lex_char('['), lex_char(']'), lex_char('(').with(predicate.or(call)));
+    // `$r [?a ?b]`, which only a relation source can match.
+    let tuple = between(lex_char('['), lex_char(']'), many::<Vec<_>, _>(value_term()));
+    let pattern = (source_var(), tuple).and_then(|(name, terms)| {
+        if name.is_empty() {
+            let message = "only a relation source can match a tuple";
+            Err(combine::primitives::Error::Message(message.into()))
+        } else {
+            Ok(WhereItem::Pattern(TuplePattern::new(name, terms)))
+        }
+    });
+    let where_item = try(pattern).or(clause.map(WhereItem::Clause)).or(bracketed);
+    let pull_expr = between(lex_char('('),
+                            lex_char(')'),
+                            keyword("pull").and(free_var()).and(pull_pattern()))
+            .map(|((_, var), pattern)| (var, Some(pattern)));
+    let find_elem = free_var().map(|var| (var, None)).or(pull_expr);
+    let find_spec = keyword("find").and(many1::<Vec<_>, _>(find_elem)).map(|x| x.1);
+    let in_item = free_var().map(InItem::Input).or(source_var().map(InItem::Source));
+    let in_spec = optional(keyword("in").with(many1::<Vec<_>, _>(in_item)));
+    let where_spec = keyword("where").and(many1::<Vec<_>, _>(where_item)).map(|x| x.1);
+
+    (find_spec, in_spec, where_spec)
+        .map(|(elems, inputs, items)| {
+            let mut query = Query::new(elems.iter().map(|&(ref var, _)| var.clone()).collect(),
+                                       vec![]);
+            for item in items {
+                match item {
+                    WhereItem::Clause(clause) => query.clauses.push(clause),
+                    WhereItem::Pattern(pattern) => query.patterns.push(pattern),
+                    WhereItem::Predicate(predicate) => query.predicates.push(predicate),
+                    WhereItem::Call(call) => query.calls.push(call),
+                }
+            }
+            for item in inputs.unwrap_or_default() {
+                match item {
+                    InItem::Input(var) => query.inputs.push(var),
+                    // `$` is the database, which is always the one queried.
+                    InItem::Source(ref name) if name.is_empty() => {}
+                    InItem::Source(name) => query.sources.push(name),
+                }
+            }
+            query.pull = elems.into_iter()
+                .filter_map(|(var, pattern)| pattern.map(|p| (var, p)))
+                .collect();
+            query
+        })
+        .and(eof())
+        .map(|x| x.0)
+}
+
+fn pull_pattern<I>() -> impl Parser<Input = I, Output = PullPattern>
+    where I: Stream<Item = char>
+{
+    // Patterns nest, so this has to go through a plain function.
+    parser(pull_pattern_)
+}
+
+fn pull_pattern_<I>(input: I) -> ParseResult<PullPattern, I>
+    where I: Stream<Item = char>
+{
+    let attr_name = || {
+        optional(char('_'))
+            .and(ident())
+            .map(|(reverse, ident)| AttrSpec { reverse: reverse.is_some(), ..AttrSpec::new(ident) })
+    };
+    let limit_expr = || {
+        keyword("limit")
+            .and(attr_name())
+            .and(count_lit())
+            .map(|((_, spec), n)| AttrSpec { limit: Some(n), ..spec })
+    };
+    let default_expr = || {
+        keyword("default")
+            .and(attr_name())
+            .and(value())
+            .map(|((_, spec), v)| AttrSpec { default: Some(v), ..spec })
+    };
+    let attr_expr = || {
+        attr_name().or(between(lex_char('('), lex_char(')'), limit_expr().or(default_expr())))
+    };
+
+    let nested = between(lex_char('{'), lex_char('}'), attr_expr().and(pull_pattern()))
+        .map(|(spec, pattern)| AttrSpec { pattern: Some(pattern), ..spec });
+    let elem = lex_char('*')
+        .map(|_| PullAttr::Wildcard)
+        .or(nested.map(PullAttr::Attr))
+        .or(attr_expr().map(PullAttr::Attr));
+
+    between(lex_char('['), lex_char(']'), many(elem))
+        .map(PullPattern)
+        .parse_stream(input)
+}
+
+fn tx_parser<I>() -> impl Parser<Input = I, Output = Tx>
+    where I: combine::Stream<Item = char>
+{
+    let fact = || {
+        between(lex_char('('),
+                lex_char(')'),
+                (entity_lit(), ident(), value()))
+                .map(|f| Fact::new(f.0, f.1, f.2))
+    };
+
+    let attr_pair = || (ident(), value());
+    let new_entity = || {
+        between(lex_char('{'),
+                lex_char('}'),
+                many1::<HashMap<_, _>, _>(attr_pair()))
+                .map(|x| TxItem::NewEntity(x))
+    };
+
+    let addition = || {
+        keyword("add")
+            .and(fact().map(|i| TxItem::Addition(i)))
+            .map(|x| x.1)
+    };
+    let retraction = || {
+        keyword("retract")
+            .and(fact().map(|i| TxItem::Retraction(i)))
+            .map(|x| x.1)
+    };
+
+    let tx_item = || choice!(addition(), retraction(), new_entity());
+
+    many1::<Vec<_>, _>(tx_item())
+        .map(|tx| Tx { items: tx })
+        .and(eof())
+        .map(|x| x.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query("find ?a where (?a name \"Bob\")").unwrap(),
+                   Query {
+                       find: vec![Var::new("a")],
+                       pull: HashMap::new(),
+                       rules: vec![],
+                       goals: vec![],
+                       inputs: vec![],
+                       sources: vec![],
+                       patterns: vec![],
+                       predicates: vec![],
+                       calls: vec![],
+                       clauses: vec![
+            Clause::new(Term::Unbound("a".into()),
+                        Term::Bound("name".into()),
+                        Term::Bound(Value::String("Bob".into()))),
+        ],
+                   })
+    }
+
+    #[test]
+    fn test_parse_pull() {
+        let q = parse_query("find ?n (pull ?e [* {parent [name]} (limit _parent 2) (default age \"?\")]) \
+                             where (?e name ?n)")
+                .unwrap();
+
+        let parent = AttrSpec {
+            pattern: Some(PullPattern(vec![PullAttr::Attr(AttrSpec::new("name"))])),
+            ..AttrSpec::new("parent")
+        };
+        let children = AttrSpec {
+            reverse: true,
+            limit: Some(2),
+            ..AttrSpec::new("parent")
+        };
+        let age = AttrSpec {
+            default: Some(Value::String("?".into())),
+            ..AttrSpec::new("age")
+        };
+
+        assert_eq!(q.find, vec![Var::new("n"), Var::new("e")]);
+        assert_eq!(q.pull[&Var::new("e")],
+                   PullPattern(vec![
+                       PullAttr::Wildcard,
+                       PullAttr::Attr(parent),
+                       PullAttr::Attr(children),
+                       PullAttr::Attr(age),
+                   ]));
+    }
+
+    #[test]
+    fn test_parse_tx() {
+        assert_eq!(parse_tx("add (0 name \"Bob\")").unwrap(),
+                   Tx {
+                       items: vec![TxItem::Addition(Fact::new(Entity(0),
+                                                              "name",
+                                                              Value::String("Bob".into())))],
+                   });
+        parse_tx("{name \"Bob\" batch \"S1'17\"}").unwrap();
+    }
+
+    #[test]
+    fn test_parse_bool_values() {
+        assert_eq!(parse_tx("add (0 active true) add (1 active false)").unwrap(),
+                   Tx {
+                       items: vec![TxItem::Addition(Fact::new(Entity(0),
+                                                              "active",
+                                                              Value::Boolean(true))),
+                                   TxItem::Addition(Fact::new(Entity(1),
+                                                              "active",
+                                                              Value::Boolean(false)))],
+                   });
+        let q = parse_query("find ?e where (?e active true) (?e flag trueish)").unwrap();
+        assert_eq!(q.clauses[0].value, Term::Bound(Value::Boolean(true)));
+        assert_eq!(q.clauses[1].value, Term::Bound(Value::Ident("trueish".into())));
+    }
+
+    #[test]
+    fn test_parsing_idents() {
+        let q = Query {
+            find: vec![Var::new("p")],
+            pull: HashMap::new(),
+            rules: vec![],
+            goals: vec![],
+            inputs: vec![],
+            sources: vec![],
+            patterns: vec![],
+            predicates: vec![],
+            calls: vec![],
+            clauses: vec![
+                Clause::new(Term::Unbound("p".into()),
+                            Term::Bound("country".into()),
+                            Term::Bound(Value::Ident("country:US".into()))
+               )
+            ]
+        };
+
+        assert_eq!(parse_query("find ?p where (?p country country:US)").unwrap(),
+                   q);
+    }
+
+    #[test]
+    fn test_parse_history_clause() {
+        let q = Query {
+            find: vec![Var::new("n"), Var::new("op")],
+            pull: HashMap::new(),
+            rules: vec![],
+            goals: vec![],
+            inputs: vec![],
+            sources: vec![],
+            patterns: vec![],
+            predicates: vec![],
+            calls: vec![],
+            clauses: vec![
+                Clause::new(Term::Bound(Entity(0)),
+                            Term::Bound("name".into()),
+                            Term::Unbound("n".into()))
+                    .with_tx(Term::Unbound("tx".into()))
+                    .with_added(Term::Unbound("op".into())),
+                Clause::new(Term::Bound(Entity(1)),
+                            Term::Bound("name".into()),
+                            Term::Unbound("n".into()))
+                    .with_tx(Term::Bound(Entity(7)))
+                    .with_added(Term::Bound(Value::Boolean(false))),
+            ]
+        };
+
+        assert_eq!(parse_query("find ?n ?op where (0 name ?n ?tx ?op) (1 name ?n 7 false)").unwrap(),
+                   q);
+    }
+
+    #[test]
+    fn test_parse_tx_clause() {
+        let q = Query {
+            find: vec![Var::new("n"), Var::new("t")],
+            pull: HashMap::new(),
+            rules: vec![],
+            goals: vec![],
+            inputs: vec![],
+            sources: vec![],
+            patterns: vec![],
+            predicates: vec![],
+            calls: vec![],
+            clauses: vec![
+                Clause::new(Term::Unbound("e".into()),
+                            Term::Bound("name".into()),
+                            Term::Unbound("n".into()))
+                    .with_tx(Term::Unbound("tx".into())),
+                Clause::new(Term::Unbound("tx".into()),
+                            Term::Bound("db:txInstant".into()),
+                            Term::Unbound("t".into())),
+            ]
+        };
+
+        assert_eq!(parse_query("find ?n ?t where (?e name ?n ?tx) (?tx db:txInstant ?t)").unwrap(),
+                   q);
+    }
+
+    #[test]
+    fn test_parse_predicates() {
+        let q = parse_query("find ?e where (?e age ?a) [(> ?a 30)] [(<= -2.5 ?a)] [(!= ?e 0)]")
+            .unwrap();
+        assert_eq!(q.clauses.len(), 1);
+        assert_eq!(q.predicates,
+                   vec![Predicate::new(Comparison::Gt, Var::new("a"), Value::Integer(30)),
+                        Predicate::new(Comparison::Le, Value::Float(Float(-2.5)), Var::new("a")),
+                        Predicate::new(Comparison::Ne, Var::new("e"), Value::Integer(0))]);
+        assert_eq!(q.predicates[0].to_string(), "[(> ?a 30)]");
+
+        assert!(parse_query("find ?e where (?e age ?a) [(>> ?a 30)]").is_err());
+    }
+
+    #[test]
+    fn test_parse_calls() {
+        let q = parse_query("find ?e ?a where (?e name ?n) [(get-else ?e age 0) ?a] \
+                             [(missing? ?e parent)] [(ground [1 2]) [?x ...]] \
+                             [(tuple ?e ?a) ?t] [(untuple ?t) [?b ?c]] \
+                             [(ground [[1 \"a\"]]) [[?i ?s]]]")
+            .unwrap();
+        assert_eq!(q.calls.len(), 6);
+        assert_eq!(q.calls[0],
+                   Call::new(Function::GetElse,
+                             vec![Var::new("e").into(),
+                                  Term::Bound(Value::Ident("age".into())),
+                                  Term::Bound(Value::Integer(0))])
+                           .bind(BindForm::Scalar(Var::new("a"))));
+        assert_eq!(q.calls[1].bind, None);
+        assert_eq!(q.calls[2].bind, Some(BindForm::Collection(Var::new("x"))));
+        assert_eq!(q.calls[4].bind,
+                   Some(BindForm::Tuple(vec![Var::new("b"), Var::new("c")])));
+        assert_eq!(q.calls[5].to_string(), "[(ground [[1 \"a\"]]) [[?i ?s]]]");
+
+        let q = parse_query("find ?to ?path where [(reachable ?e parent ?to)] \
+                             [(reachable ?e parent 2 ?to)] [(reachable ?e parent ?d) ?x] \
+                             [(shortest-path ?e ?to parent ?path)]")
+            .unwrap();
+        assert_eq!(q.calls[0].to_string(), "[(reachable ?e parent) [?to ...]]");
+        assert_eq!(q.calls[1].to_string(), "[(reachable ?e parent 2) [?to ...]]");
+        assert_eq!(q.calls[2].to_string(), "[(reachable ?e parent ?d) ?x]");
+        assert_eq!(q.calls[3].to_string(), "[(shortest-path ?e ?to parent) ?path]");
+
+        assert!(parse_query("find ?e where (?e name ?n) [(frobnicate ?e) ?x]").is_err());
+        assert!(parse_query("find ?e where (?e name ?n) [(ground [1]) [?x ?y ...]]").is_err());
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let q = parse_query("find ?n in $ $old ?e where (?e name ?n) $old (?e name ?n)").unwrap();
+        assert_eq!(q.sources, vec!["old".to_string()]);
+        assert_eq!(q.inputs, vec![Var::new("e")]);
+        assert_eq!(q.clauses[0].source, None);
+        assert_eq!(q.clauses[1].source, Some("old".into()));
+        assert_eq!(q.clauses[1].to_string(), "$old (?e name ?n)");
+
+        let q = parse_query("find ?n in $r where $r [?n 30 \"x\"] (?e name ?n) $r []").unwrap();
+        assert_eq!(q.patterns,
+                   vec![TuplePattern::new("r",
+                                          vec![Var::new("n").into(),
+                                               Term::Bound(Value::Integer(30)),
+                                               Term::Bound(Value::String("x".into()))]),
+                        TuplePattern::new("r", vec![])]);
+        assert_eq!(q.patterns[0].to_string(), "$r [?n 30 \"x\"]");
+        assert_eq!(q.clauses.len(), 1);
+        assert!(parse_query("find ?n where $ [?n]").is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Queries compiled once and then run many times, perhaps against
+//! different `Db`s of the same database.
+
+use std::collections::{HashMap, HashSet};
+
+use db::Db;
+use explain::{self, Access};
+use functions::{self, Step};
+use query::{self, Call, Clause, Predicate, Query, Term, TuplePattern, var};
+use sources::{self, Source};
+use {Binding, Entity, Error, QueryResult, Result, Value};
+
+/// A query with its attributes resolved and an index chosen for each
+/// clause, ready to be run with `execute`.
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    query: Query,
+    /// In the order they're run.
+    steps: Vec<PreparedStep>,
+    /// Each attribute the plan refers to in the database it's run
+    /// against and its entity, so that we can tell whether a `Db`
+    /// still has the same schema.
+    schema: Vec<(String, Entity)>,
+}
+
+#[derive(Debug, Clone)]
+enum PreparedStep {
+    Match(Clause, ClausePlan),
+    Tuples(TuplePattern),
+    Call(Call),
+    Goals,
+}
+
+/// What's worked out in advance about matching a clause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ClausePlan {
+    /// The attribute's entity, if the attribute is a constant and the
+    /// clause is matched against the database the query is run
+    /// against.
+    pub attribute: Option<Entity>,
+    pub access: Access,
+    /// For `Access::AvetRange`, the predicates that bound the range.
+    pub range: Vec<Predicate>,
+}
+
+impl Db {
+    /// Checks `query` and works out how to run it. The result can be
+    /// run against this `Db` and any later one, as long as the
+    /// attributes it refers to haven't changed.
+    pub fn prepare(&self, query: &Query) -> Result<PreparedQuery> {
+        query.validate()?;
+        self.check_schema(query)?;
+
+        let mut bound = query.inputs.iter().cloned().collect::<HashSet<_>>();
+        let mut steps = vec![];
+        let mut schema = vec![];
+        for step in functions::schedule(query)? {
+            let clause = match step {
+                Step::Match(clause) => clause,
+                Step::Call(call) => {
+                    bound.extend(call.bind.iter().flat_map(|form| form.vars()).cloned());
+                    steps.push(PreparedStep::Call(call.clone()));
+                    continue;
+                }
+                Step::Tuples(pattern) => {
+                    bound.extend(pattern.vars().into_iter().cloned());
+                    steps.push(PreparedStep::Tuples(pattern.clone()));
+                    continue;
+                }
+                Step::Goals => {
+                    steps.push(PreparedStep::Goals);
+                    continue;
+                }
+            };
+            let attribute = match clause.attribute {
+                Term::Bound(_) if clause.source.is_some() => None,
+                Term::Bound(ref a) => {
+                    let attr = self.attribute_entity(a)?;
+                    schema.push((a.clone(), attr));
+                    Some(attr)
+                }
+                Term::Unbound(_) => None,
+            };
+            let (access, range) = explain::access(clause, &bound, &query.predicates);
+            let plan = ClausePlan {
+                attribute,
+                access,
+                range,
+            };
+            steps.push(PreparedStep::Match(clause.clone(), plan));
+            bound.extend(clause.vars().into_iter().cloned());
+        }
+
+        Ok(PreparedQuery {
+               query: query.clone(),
+               steps,
+               schema,
+           })
+    }
+}
+
+impl PreparedQuery {
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Runs the query against `db`. `inputs` gives a value for each of
+    /// the query's inputs, as in `&[("?name", "Bob".into())]`.
+    pub fn execute(&self, db: &Db, inputs: &[(&str, Value)]) -> Result<QueryResult> {
+        self.execute_with_sources(db, &[], inputs)
+    }
+
+    /// Runs the query against `db`, matching clauses and tuple
+    /// patterns that name a source against the one given for it in
+    /// `sources`, as in `&[("$old", Source::Db(&old_db))]`.
+    pub fn execute_with_sources(&self,
+                                db: &Db,
+                                sources: &[(&str, Source)],
+                                inputs: &[(&str, Value)])
+                                -> Result<QueryResult> {
+        for &(ref ident, attr) in &self.schema {
+            if db.idents.get_entity(ident.clone()) != Some(attr) {
+                return Err(Error::Schema(format!("{} has changed since the query was prepared",
+                                                 ident)));
+            }
+        }
+
+        let sources = self.bind_sources(sources)?;
+        let inputs = self.bind(inputs)?;
+        match db.cache {
+            // Other sources aren't part of the cache's key.
+            Some(ref cache) if sources.is_empty() => {
+                cache.get_or_run(db,
+                                 &self.query,
+                                 &inputs,
+                                 || self.run(db, &sources, inputs.clone()))
+            }
+            _ => self.run(db, &sources, inputs),
+        }
+    }
+
+    fn run(&self,
+           db: &Db,
+           sources: &HashMap<&str, Source>,
+           inputs: Binding)
+           -> Result<QueryResult> {
+        let mut bindings = vec![inputs];
+        for step in &self.steps {
+            let (clause, plan) = match *step {
+                PreparedStep::Match(ref clause, ref plan) => (clause, plan),
+                PreparedStep::Call(ref call) => {
+                    bindings = db.call(call, bindings)?;
+                    bindings = query::filter(&self.query.predicates, bindings);
+                    continue;
+                }
+                PreparedStep::Tuples(ref pattern) => {
+                    bindings = match sources[&pattern.source[..]] {
+                        Source::Relation(tuples) => {
+                            sources::match_tuples(pattern, tuples, bindings)
+                        }
+                        Source::Db(_) => {
+                            return Err(Error::Validation(format!("${} is a database, so it \
+                                                                  matches clauses, not {}",
+                                                                 pattern.source,
+                                                                 pattern)))
+                        }
+                    };
+                    db.check_limits(bindings.len())?;
+                    bindings = query::filter(&self.query.predicates, bindings);
+                    continue;
+                }
+                PreparedStep::Goals => {
+                    bindings = db.solve_goals(&self.query.rules, &self.query.goals, bindings)?;
+                    bindings = query::filter(&self.query.predicates, bindings);
+                    continue;
+                }
+            };
+            bindings = match clause.source {
+                None => db.match_clause(clause, Some(plan), bindings)?,
+                Some(ref name) => {
+                    let bindings = match sources[&name[..]] {
+                        Source::Db(other) => other.match_clause(clause, Some(plan), bindings)?,
+                        Source::Relation(_) => {
+                            return Err(Error::Validation(format!("${} is a relation, so it \
+                                                                  matches tuples such as ${} \
+                                                                  [?a ?b], not {}",
+                                                                 name,
+                                                                 name,
+                                                                 clause)))
+                        }
+                    };
+                    db.check_limits(bindings.len())?;
+                    bindings
+                }
+            };
+            bindings = query::filter(&self.query.predicates, bindings);
+        }
+        bindings = query::filter(&self.query.predicates, bindings);
+        db.project(&self.query, bindings)
+    }
+
+    fn bind_sources<'a>(&self,
+                        sources: &[(&'a str, Source<'a>)])
+                        -> Result<HashMap<&'a str, Source<'a>>> {
+        let mut bound = HashMap::new();
+        for &(name, source) in sources {
+            let name = name.trim_left_matches('$');
+            if !self.query.sources.iter().any(|s| s == name) {
+                return Err(Error::Validation(format!("${} is not a source of the query", name)));
+            }
+            bound.insert(name, source);
+        }
+
+        match self.query.sources.iter().find(|s| !bound.contains_key(&s[..])) {
+            Some(missing) => Err(Error::Validation(format!("no source given for ${}", missing))),
+            None => Ok(bound),
+        }
+    }
+
+    fn bind(&self, inputs: &[(&str, Value)]) -> Result<Binding> {
+        let mut binding = HashMap::new();
+        for &(name, ref value) in inputs {
+            let input = var(name);
+            if !self.query.inputs.contains(&input) {
+                return Err(Error::Validation(format!("?{} is not an input of the query",
+                                                     input.name)));
+            }
+            binding.insert(input, value.clone());
+        }
+
+        match self.query.inputs.iter().find(|input| !binding.contains_key(input)) {
+            Some(missing) => {
+                Err(Error::Validation(format!("no value given for input ?{}", missing.name)))
+            }
+            None => Ok(binding),
+        }
+    }
+}
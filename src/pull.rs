@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use db::Db;
+use {Entity, Error, Result, Value};
+
+/// A pull pattern describes which attributes of an entity to fetch,
+/// e.g. `[name {parent [name]} _parent]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PullPattern(pub Vec<PullAttr>);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PullAttr {
+    /// `*`: every attribute of the entity, plus its `db:id`.
+    Wildcard,
+    Attr(AttrSpec),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AttrSpec {
+    pub ident: String,
+    /// Written `_ident`: follows refs *to* the entity instead of
+    /// from it.
+    pub reverse: bool,
+    pub limit: Option<usize>,
+    pub default: Option<Value>,
+    /// If set, ref values are pulled with this pattern instead of
+    /// being returned as bare entities.
+    pub pattern: Option<PullPattern>,
+}
+
+impl AttrSpec {
+    pub fn new<S: Into<String>>(ident: S) -> AttrSpec {
+        AttrSpec {
+            ident: ident.into(),
+            reverse: false,
+            limit: None,
+            default: None,
+            pattern: None,
+        }
+    }
+
+    fn key(&self) -> String {
+        if self.reverse {
+            format!("_{}", self.ident)
+        } else {
+            self.ident.clone()
+        }
+    }
+}
+
+impl Db {
+    /// Fetches the attributes of `entity` described by `pattern` as a
+    /// `Value::Map` keyed by ident. An attribute with more than one
+    /// value, and every reverse attribute, is given as a `Value::List`.
+    pub fn pull(&self, pattern: &PullPattern, entity: Entity) -> Result<Value> {
+        let mut result = BTreeMap::new();
+
+        for attr in &pattern.0 {
+            match *attr {
+                PullAttr::Wildcard => {
+                    result.insert("db:id".to_string(), Value::Entity(entity));
+                    let mut by_attr: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+                    for rec in self.entity_records(entity)? {
+                        let key = self.idents
+                            .get_ident(rec.attribute)
+                            .unwrap_or(format!("{}", rec.attribute.0));
+                        by_attr.entry(key).or_insert_with(Vec::new).push(rec.value);
+                    }
+                    for (key, values) in by_attr {
+                        result.insert(key, collapse(values));
+                    }
+                }
+                PullAttr::Attr(ref spec) => {
+                    let attr = match self.idents.get_entity(spec.ident.clone()) {
+                        Some(attr) => attr,
+                        None => {
+                            return Err(Error::Schema(format!("unknown attribute {}", spec.ident)))
+                        }
+                    };
+
+                    let mut values = if spec.reverse {
+                        self.referrers(entity, attr)?
+                            .into_iter()
+                            .map(Value::Entity)
+                            .collect::<Vec<_>>()
+                    } else {
+                        self.attribute_values(entity, attr)?
+                    };
+
+                    if let Some(limit) = spec.limit {
+                        values.truncate(limit);
+                    }
+
+                    if let Some(ref nested) = spec.pattern {
+                        values = values
+                            .into_iter()
+                            .map(|v| match self.idents.resolve(&v) {
+                                     Some(e) => self.pull(nested, e),
+                                     None => Ok(v),
+                                 })
+                            .collect::<Result<Vec<_>>>()?;
+                    }
+
+                    if values.is_empty() {
+                        if let Some(ref default) = spec.default {
+                            result.insert(spec.key(), default.clone());
+                        }
+                    } else if spec.reverse {
+                        result.insert(spec.key(), Value::List(values));
+                    } else {
+                        result.insert(spec.key(), collapse(values));
+                    }
+                }
+            }
+        }
+
+        Ok(Value::Map(result))
+    }
+
+    pub fn pull_many(&self, pattern: &PullPattern, entities: &[Entity]) -> Result<Vec<Value>> {
+        entities.iter().map(|&e| self.pull(pattern, e)).collect()
+    }
+}
+
+fn collapse(mut values: Vec<Value>) -> Value {
+    if values.len() == 1 {
+        values.pop().unwrap()
+    } else {
+        Value::List(values)
+    }
+}
@@ -1,10 +1,36 @@
-use model::{Entity, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use model::{Entity, Float, Value};
+use pull::PullPattern;
+use {Binding, Error, Result};
 
 // A query looks like `find ?var where (?var <attribute> <value>)`
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Query {
     pub find: Vec<Var>,
     pub clauses: Vec<Clause>,
+    /// Comparisons that each result must pass, as in `[(> ?age 30)]`.
+    pub predicates: Vec<Predicate>,
+    /// Calls to built-in functions, as in `[(get-else ?e age 0) ?age]`.
+    pub calls: Vec<Call>,
+    /// Find variables written as `(pull ?var [...])`, whose entities
+    /// are replaced in the results by the pulled attributes.
+    pub pull: HashMap<Var, PullPattern>,
+    /// Rules that `goals` can refer to.
+    pub rules: Vec<Rule>,
+    /// Atoms that must also hold, matched after the clauses.
+    pub goals: Vec<Atom>,
+    /// Variables whose values are given each time a prepared query is
+    /// run, as in `find ?child in ?name where ...`.
+    pub inputs: Vec<Var>,
+    /// Data sources other than the database the query is run against,
+    /// as in `find ?n in $old where $old (?e name ?n)`. The names don't
+    /// include the `$`.
+    pub sources: Vec<String>,
+    /// Tuples matched against relation sources, as in `$r [?a ?b]`.
+    pub patterns: Vec<TuplePattern>,
 }
 
 impl Query {
@@ -12,8 +38,183 @@ impl Query {
         Query {
             find: find,
             clauses: clauses,
+            predicates: vec![],
+            calls: vec![],
+            pull: HashMap::new(),
+            rules: vec![],
+            goals: vec![],
+            inputs: vec![],
+            sources: vec![],
+            patterns: vec![],
+        }
+    }
+
+    /// Starts building a query for the given variables, e.g.
+    ///
+    /// ```ignore
+    /// let q = Query::find(&["?name"])
+    ///     .where_(var("?person"), "name", "Bob")
+    ///     .where_(var("?person"), "parent", var("?child"))
+    ///     .where_(var("?child"), "name", var("?name"))
+    ///     .build()?;
+    /// ```
+    pub fn find(vars: &[&str]) -> QueryBuilder {
+        QueryBuilder { query: Query::new(vars.iter().map(|v| var(*v)).collect(), vec![]) }
+    }
+
+    /// Checks that the query can be run: it finds at least one
+    /// variable, each of them and each variable a predicate or call
+    /// reads is bound by a clause, tuple pattern, goal, input or call,
+    /// and each call has the right number of arguments.
+    pub fn validate(&self) -> Result<()> {
+        if self.find.is_empty() {
+            return Err(Error::Validation("a query must find at least one variable".into()));
+        }
+
+        let mut bound = self.inputs.iter().collect::<Vec<_>>();
+        for clause in &self.clauses {
+            bound.extend(clause.vars());
+        }
+        for pattern in &self.patterns {
+            bound.extend(pattern.vars());
+        }
+        for goal in &self.goals {
+            bound.extend(goal.args.iter().filter_map(Term::var));
+        }
+        for call in &self.calls {
+            bound.extend(call.bind.iter().flat_map(BindForm::vars));
+        }
+
+        for call in &self.calls {
+            call.function.check_arity(call.args.len())?;
         }
+
+        let compared = self.predicates.iter().flat_map(|p| p.vars());
+        let read = self.calls.iter().flat_map(|c| c.args.iter().filter_map(Term::var));
+        for var in self.find.iter().chain(compared).chain(read) {
+            if !bound.contains(&var) {
+                return Err(Error::Validation(format!("?{} is not bound by any clause", var.name)));
+            }
+        }
+        for var in self.pull.keys() {
+            if !self.find.contains(var) {
+                return Err(Error::Validation(format!("?{} is pulled but not found", var.name)));
+            }
+        }
+        let patterns = self.patterns.iter().map(|p| &p.source);
+        for source in self.clauses.iter().filter_map(|c| c.source.as_ref()).chain(patterns) {
+            if !self.sources.contains(source) {
+                return Err(Error::Validation(format!("${} is not a source of the query", source)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `Query` one clause at a time. See `Query::find`.
+#[derive(Debug)]
+pub struct QueryBuilder {
+    query: Query,
+}
+
+impl QueryBuilder {
+    /// Adds the clause `(entity attribute value)`. Entities and
+    /// attributes can be given directly or as a `var`; values can
+    /// also be strings, bools, integers and so on.
+    pub fn where_<E, A, V>(self, entity: E, attribute: A, value: V) -> QueryBuilder
+        where E: Into<Term<Entity>>,
+              A: Into<Term<String>>,
+              V: Into<Term<Value>>
+    {
+        self.clause(Clause::new(entity.into(), attribute.into(), value.into()))
+    }
+
+    /// Declares variables whose values will be given when the query
+    /// is run with `PreparedQuery::execute`.
+    pub fn inputs(mut self, vars: &[&str]) -> QueryBuilder {
+        self.query.inputs.extend(vars.iter().map(|v| var(*v)));
+        self
+    }
+
+    /// Declares data sources that clauses can be matched against
+    /// instead of the database. See `PreparedQuery::execute_with_sources`.
+    pub fn sources(mut self, names: &[&str]) -> QueryBuilder {
+        self.query.sources.extend(names.iter().map(|n| n.trim_left_matches('$').to_string()));
+        self
     }
+
+    /// Adds a clause built by hand, e.g. one with a `tx` term.
+    pub fn clause(mut self, clause: Clause) -> QueryBuilder {
+        self.query.clauses.push(clause);
+        self
+    }
+
+    /// Adds a predicate, e.g.
+    /// `Predicate::new(Comparison::Gt, var("?age"), 30i64)`.
+    pub fn predicate(mut self, predicate: Predicate) -> QueryBuilder {
+        self.query.predicates.push(predicate);
+        self
+    }
+
+    /// Adds a call to a built-in function, e.g.
+    /// `Call::new(Function::Ground, vec![Term::Bound(Value::List(ids))])
+    /// .bind(BindForm::Collection(var("?e")))`.
+    pub fn call(mut self, call: Call) -> QueryBuilder {
+        self.query.calls.push(call);
+        self
+    }
+
+    /// Replaces the entity found for `v` with the attributes `pattern`
+    /// pulls from it.
+    pub fn pull(mut self, v: &str, pattern: PullPattern) -> QueryBuilder {
+        self.query.pull.insert(var(v), pattern);
+        self
+    }
+
+    pub fn build(self) -> Result<Query> {
+        self.query.validate()?;
+        Ok(self.query)
+    }
+}
+
+/// A variable for use with `QueryBuilder`. The leading `?` is
+/// optional, so `var("?e")` and `var("e")` are the same variable.
+pub fn var(name: &str) -> Var {
+    Var::new(name.trim_left_matches('?'))
+}
+
+/// A predicate applied to some terms, e.g. `ancestor(A, B)`. If no
+/// rule defines the predicate, it's taken to be an attribute, and the
+/// atom stands for the clause `(A ancestor B)`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Atom {
+    pub predicate: String,
+    pub args: Vec<Term<Value>>,
+}
+
+impl Atom {
+    pub fn new<S: Into<String>>(predicate: S, args: Vec<Term<Value>>) -> Atom {
+        Atom {
+            predicate: predicate.into(),
+            args: args,
+        }
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let args = self.args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        write!(f, "{}({})", self.predicate, args.join(", "))
+    }
+}
+
+/// A Horn clause: `head` holds wherever every atom in `body` does.
+/// A predicate can be defined by several rules, and rules can be
+/// recursive.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Rule {
+    pub head: Atom,
+    pub body: Vec<Atom>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -21,6 +222,15 @@ pub struct Clause {
     pub entity: Term<Entity>,
     pub attribute: Term<String>,
     pub value: Term<Value>,
+    /// The transaction that asserted or retracted the fact, if the
+    /// clause names one.
+    pub tx: Option<Term<Entity>>,
+    /// Whether the fact was added (`true`) or retracted (`false`).
+    /// Only history views ever see retractions.
+    pub added: Option<Term<Value>>,
+    /// The source the clause is matched against, without its `$`, or
+    /// `None` for the database the query is run against.
+    pub source: Option<String>,
 }
 
 impl Clause {
@@ -29,8 +239,428 @@ impl Clause {
             entity: e,
             attribute: a,
             value: v,
+            tx: None,
+            added: None,
+            source: None,
         }
     }
+
+    pub fn with_tx(self, tx: Term<Entity>) -> Clause {
+        Clause { tx: Some(tx), ..self }
+    }
+
+    pub fn with_added(self, added: Term<Value>) -> Clause {
+        Clause { added: Some(added), ..self }
+    }
+
+    /// The clause matched against the source `$name` instead of the
+    /// database.
+    pub fn with_source<S: Into<String>>(self, name: S) -> Clause {
+        Clause { source: Some(name.into()), ..self }
+    }
+
+    /// The variables in the clause.
+    pub fn vars(&self) -> Vec<&Var> {
+        let mut vars = vec![];
+        vars.extend(self.entity.var());
+        vars.extend(self.attribute.var());
+        vars.extend(self.value.var());
+        vars.extend(self.tx.as_ref().and_then(Term::var));
+        vars.extend(self.added.as_ref().and_then(Term::var));
+        vars
+    }
+}
+
+impl Display for Clause {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(ref source) = self.source {
+            write!(f, "${} ", source)?;
+        }
+        write!(f, "({} {} {}", self.entity, self.attribute, self.value)?;
+        if let Some(ref tx) = self.tx {
+            write!(f, " {}", tx)?;
+        }
+        if let Some(ref added) = self.added {
+            write!(f, " {}", added)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A tuple matched by position against a relation source, written
+/// `$r [?name ?age]`. It matches each tuple of the relation with as
+/// many values as it has terms.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TuplePattern {
+    pub source: String,
+    pub terms: Vec<Term<Value>>,
+}
+
+impl TuplePattern {
+    pub fn new<S: Into<String>>(source: S, terms: Vec<Term<Value>>) -> TuplePattern {
+        TuplePattern {
+            source: source.into(),
+            terms: terms,
+        }
+    }
+
+    /// The variables in the pattern.
+    pub fn vars(&self) -> Vec<&Var> {
+        self.terms.iter().filter_map(Term::var).collect()
+    }
+}
+
+impl Display for TuplePattern {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "${} [", self.source)?;
+        for (i, term) in self.terms.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { " " }, term)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A comparison of two terms, written `[(> ?age 30)]`. Numbers of
+/// every type can be compared with each other, but other values can
+/// only be compared with values of the same type; `[(< ?a ?b)]` fails
+/// if `?a` is a string and `?b` a number.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Predicate {
+    pub op: Comparison,
+    pub left: Term<Value>,
+    pub right: Term<Value>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Predicate {
+    pub fn new<L, R>(op: Comparison, left: L, right: R) -> Predicate
+        where L: Into<Term<Value>>,
+              R: Into<Term<Value>>
+    {
+        Predicate {
+            op: op,
+            left: left.into(),
+            right: right.into(),
+        }
+    }
+
+    pub fn vars(&self) -> Vec<&Var> {
+        self.left.var().into_iter().chain(self.right.var()).collect()
+    }
+
+    /// Whether the predicate holds under `binding`, or `None` if it
+    /// compares a variable `binding` doesn't bind.
+    pub(crate) fn test(&self, binding: &Binding) -> Option<bool> {
+        fn value<'a>(term: &'a Term<Value>, binding: &'a Binding) -> Option<&'a Value> {
+            match *term {
+                Term::Bound(ref v) => Some(v),
+                Term::Unbound(ref var) => binding.get(var),
+            }
+        }
+        match (value(&self.left, binding), value(&self.right, binding)) {
+            (Some(left), Some(right)) => Some(self.op.holds(left.compare(right))),
+            _ => None,
+        }
+    }
+
+    /// The comparison of `var` with the other term, if the predicate
+    /// orders `var` against something else, e.g. `(Gt, 30)` for both
+    /// `[(> ?a 30)]` and `[(< 30 ?a)]`.
+    pub(crate) fn bound_on(&self, var: &Var) -> Option<(Comparison, &Term<Value>)> {
+        let (op, other) = match (self.left.var(), self.right.var()) {
+            (Some(left), Some(right)) if left == right => return None,
+            (Some(left), _) if left == var => (self.op, &self.right),
+            (_, Some(right)) if right == var => (self.op.flip(), &self.left),
+            _ => return None,
+        };
+        match op {
+            Comparison::Eq | Comparison::Ne => None,
+            _ => Some((op, other)),
+        }
+    }
+}
+
+impl Comparison {
+    pub fn from_symbol(symbol: &str) -> Option<Comparison> {
+        match symbol {
+            "=" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    /// The comparison with its operands swapped, so that `a < b` is
+    /// `b > a`.
+    pub fn flip(&self) -> Comparison {
+        match *self {
+            Comparison::Lt => Comparison::Gt,
+            Comparison::Le => Comparison::Ge,
+            Comparison::Gt => Comparison::Lt,
+            Comparison::Ge => Comparison::Le,
+            op => op,
+        }
+    }
+
+    /// Whether values ordered as `ordering` pass the comparison. Values
+    /// that can't be ordered are unequal, and fail every other test.
+    fn holds(&self, ordering: Option<Ordering>) -> bool {
+        match (*self, ordering) {
+            (Comparison::Ne, ordering) => ordering != Some(Ordering::Equal),
+            (_, None) => false,
+            (Comparison::Eq, Some(o)) => o == Ordering::Equal,
+            (Comparison::Lt, Some(o)) => o == Ordering::Less,
+            (Comparison::Le, Some(o)) => o != Ordering::Greater,
+            (Comparison::Gt, Some(o)) => o == Ordering::Greater,
+            (Comparison::Ge, Some(o)) => o != Ordering::Less,
+        }
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "[({} {} {})]", self.op.symbol(), self.left, self.right)
+    }
+}
+
+/// A call to a built-in function, written `[(get-else ?e age 0) ?age]`.
+/// The result is bound to the variables of `bind`; a call without
+/// them keeps only the bindings for which it doesn't return `false`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Call {
+    pub function: Function,
+    pub args: Vec<Term<Value>>,
+    pub bind: Option<BindForm>,
+}
+
+impl Call {
+    pub fn new(function: Function, args: Vec<Term<Value>>) -> Call {
+        Call {
+            function: function,
+            args: args,
+            bind: None,
+        }
+    }
+
+    pub fn bind(self, bind: BindForm) -> Call {
+        Call { bind: Some(bind), ..self }
+    }
+
+    /// Reads a variable after the arguments of `reachable` or
+    /// `shortest-path` as what they bind, so that
+    /// `(reachable ?from parent ?to)` is the same call as
+    /// `[(reachable ?from parent) [?to ...]]` and
+    /// `(shortest-path ?a ?b parent ?path)` the same as
+    /// `[(shortest-path ?a ?b parent) ?path]`. A depth given as a
+    /// variable needs the bind form to be written out.
+    pub fn with_output_arg(mut self) -> Call {
+        let form: fn(Var) -> BindForm = match self.function {
+            Function::Reachable => BindForm::Collection,
+            Function::ShortestPath => BindForm::Scalar,
+            _ => return self,
+        };
+        if self.bind.is_some() || self.args.len() <= self.function.arity().0 {
+            return self;
+        }
+        let var = match self.args.last() {
+            Some(&Term::Unbound(ref var)) => var.clone(),
+            _ => return self,
+        };
+        self.args.pop();
+        self.bind(form(var))
+    }
+
+    /// The variables the call reads.
+    pub fn inputs(&self) -> Vec<&Var> {
+        self.args.iter().filter_map(Term::var).collect()
+    }
+}
+
+impl Display for Call {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "[({}", self.function.name())?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        write!(f, ")")?;
+        if let Some(ref bind) = self.bind {
+            write!(f, " {}", bind)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Function {
+    /// `(get-else ?e attr default)`: the value of `attr` on `?e`, or
+    /// `default` if it has none.
+    GetElse,
+    /// `(missing? ?e attr)`: whether `?e` has no value for `attr`.
+    Missing,
+    /// `(ground value)`: the value, e.g. `[(ground [1 2]) [?x ...]]`.
+    Ground,
+    /// `(tuple ?a ?b ...)`: a list of the arguments.
+    Tuple,
+    /// `(untuple ?t)`: the list `?t`, to be bound as `[?a ?b ...]`.
+    Untuple,
+    /// `(identity ?x)`: `?x`.
+    Identity,
+    /// `(reachable ?e attr ?to)`: the entities reached by following
+    /// `attr` from `?e` one or more times, or backwards if it's written
+    /// `_attr`. A depth before `?to` limits how many times it's
+    /// followed.
+    Reachable,
+    /// `(shortest-path ?a ?b attr ?path)`: a list of the entities on
+    /// the shortest path from `?a` to `?b` along `attr`, including
+    /// both. A depth before `?path` limits the length of the path. If
+    /// there's no path, the binding is dropped.
+    ShortestPath,
+    /// `(fulltext attr "words")`: a list of `[entity value score]` for
+    /// each value of the `db:fulltext` attribute `attr` containing any
+    /// of the words, where the score is the fraction of them it
+    /// contains.
+    Fulltext,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "get-else" => Some(Function::GetElse),
+            "missing?" => Some(Function::Missing),
+            "ground" => Some(Function::Ground),
+            "tuple" => Some(Function::Tuple),
+            "untuple" => Some(Function::Untuple),
+            "identity" => Some(Function::Identity),
+            "reachable" => Some(Function::Reachable),
+            "shortest-path" => Some(Function::ShortestPath),
+            "fulltext" => Some(Function::Fulltext),
+            _ => None,
+        }
+    }
+
+    /// The positions of the arguments that name entities.
+    pub fn entity_args(&self) -> &'static [usize] {
+        match *self {
+            Function::GetElse | Function::Missing | Function::Reachable => &[0],
+            Function::ShortestPath => &[0, 1],
+            _ => &[],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Function::GetElse => "get-else",
+            Function::Missing => "missing?",
+            Function::Ground => "ground",
+            Function::Tuple => "tuple",
+            Function::Untuple => "untuple",
+            Function::Identity => "identity",
+            Function::Reachable => "reachable",
+            Function::ShortestPath => "shortest-path",
+            Function::Fulltext => "fulltext",
+        }
+    }
+
+    /// The fewest and most arguments the function takes, where `None`
+    /// means there's no most.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        match *self {
+            Function::GetElse => (3, Some(3)),
+            Function::Missing | Function::Fulltext => (2, Some(2)),
+            Function::Tuple => (1, None),
+            Function::Ground | Function::Untuple | Function::Identity => (1, Some(1)),
+            Function::Reachable => (2, Some(3)),
+            Function::ShortestPath => (3, Some(4)),
+        }
+    }
+
+    /// Checks that the function takes `n` arguments.
+    pub fn check_arity(&self, n: usize) -> Result<()> {
+        let takes = match self.arity() {
+            (min, Some(max)) if n < min || n > max => {
+                if min == max {
+                    format!("{}", min)
+                } else {
+                    format!("{} to {}", min, max)
+                }
+            }
+            (min, None) if n < min => format!("at least {}", min),
+            _ => return Ok(()),
+        };
+        Err(Error::Validation(format!("{} takes {} arguments, not {}", self.name(), takes, n)))
+    }
+}
+
+/// How a call's result is bound to variables.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BindForm {
+    /// `?x`: the result.
+    Scalar(Var),
+    /// `[?a ?b]`: each item of a list of that length.
+    Tuple(Vec<Var>),
+    /// `[?x ...]`: each item of a list in turn.
+    Collection(Var),
+    /// `[[?a ?b]]`: each item of a list of tuples in turn.
+    Relation(Vec<Var>),
+}
+
+impl BindForm {
+    pub fn vars(&self) -> Vec<&Var> {
+        match *self {
+            BindForm::Scalar(ref var) |
+            BindForm::Collection(ref var) => vec![var],
+            BindForm::Tuple(ref vars) |
+            BindForm::Relation(ref vars) => vars.iter().collect(),
+        }
+    }
+}
+
+impl Display for BindForm {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let join = |vars: &[Var]| vars.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ");
+        match *self {
+            BindForm::Scalar(ref var) => write!(f, "{}", var),
+            BindForm::Tuple(ref vars) => write!(f, "[{}]", join(vars)),
+            BindForm::Collection(ref var) => write!(f, "[{} ...]", var),
+            BindForm::Relation(ref vars) => write!(f, "[[{}]]", join(vars)),
+        }
+    }
+}
+
+/// Keeps the bindings that pass every predicate whose variables they
+/// bind. Predicates comparing variables that aren't bound yet are
+/// left for later.
+pub(crate) fn filter(predicates: &[Predicate], bindings: Vec<Binding>) -> Vec<Binding> {
+    if predicates.is_empty() {
+        return bindings;
+    }
+    bindings
+        .into_iter()
+        .filter(|binding| predicates.iter().all(|p| p.test(binding) != Some(false)))
+        .collect()
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -39,6 +669,84 @@ pub enum Term<T> {
     Unbound(Var),
 }
 
+impl<T> Term<T> {
+    pub fn var(&self) -> Option<&Var> {
+        match *self {
+            Term::Unbound(ref var) => Some(var),
+            Term::Bound(_) => None,
+        }
+    }
+}
+
+impl<T: Display> Display for Term<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Term::Bound(ref x) => write!(f, "{}", x),
+            Term::Unbound(ref var) => write!(f, "{}", var),
+        }
+    }
+}
+
+impl<T> From<Var> for Term<T> {
+    fn from(var: Var) -> Self {
+        Term::Unbound(var)
+    }
+}
+
+impl From<Entity> for Term<Entity> {
+    fn from(e: Entity) -> Self {
+        Term::Bound(e)
+    }
+}
+
+impl<'a> From<&'a str> for Term<String> {
+    fn from(attribute: &'a str) -> Self {
+        Term::Bound(attribute.to_string())
+    }
+}
+
+impl From<Value> for Term<Value> {
+    fn from(v: Value) -> Self {
+        Term::Bound(v)
+    }
+}
+
+impl From<Entity> for Term<Value> {
+    fn from(e: Entity) -> Self {
+        Term::Bound(Value::Entity(e))
+    }
+}
+
+impl From<f64> for Term<Value> {
+    fn from(x: f64) -> Self {
+        Term::Bound(Value::Float(Float(x)))
+    }
+}
+
+impl<'a> From<&'a str> for Term<Value> {
+    fn from(s: &'a str) -> Self {
+        Term::Bound(Value::String(s.to_string()))
+    }
+}
+
+impl From<String> for Term<Value> {
+    fn from(s: String) -> Self {
+        Term::Bound(Value::String(s))
+    }
+}
+
+impl From<bool> for Term<Value> {
+    fn from(b: bool) -> Self {
+        Term::Bound(Value::Boolean(b))
+    }
+}
+
+impl From<i64> for Term<Value> {
+    fn from(n: i64) -> Self {
+        Term::Bound(Value::Integer(n))
+    }
+}
+
 // A free [logic] variable
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct Var {
@@ -56,3 +764,9 @@ impl<T: Into<String>> From<T> for Var {
         Var { name: x.into() }
     }
 }
+
+impl Display for Var {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "?{}", self.name)
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use db::Db;
+use query::{Atom, Clause, Rule, Term};
+use {Binding, Error, Result, Value};
+
+/// The tuples derived so far for each predicate defined by a rule.
+type Relations = HashMap<String, BTreeSet<Vec<Value>>>;
+
+impl Db {
+    /// Extends each of `bindings` with every way of satisfying all of
+    /// `goals`, given `rules`.
+    pub(crate) fn solve_goals(&self,
+                              rules: &[Rule],
+                              goals: &[Atom],
+                              bindings: Vec<Binding>)
+                              -> Result<Vec<Binding>> {
+        let relations = self.derive(&reached(rules, goals))?;
+        let (clauses, calls) = split(goals, &relations)?;
+        let bindings = join_all(&calls, self.solve(&clauses, bindings)?, &relations);
+        self.check_limits(bindings.len())?;
+        Ok(bindings)
+    }
+
+    /// Evaluates `rules` bottom-up and semi-naively: after the first
+    /// round, a rule is only applied where one of the rules it calls
+    /// derived a new tuple in the round before, until no rule adds a
+    /// new one. Attribute atoms in rule bodies don't depend on the
+    /// derived tuples, so they're only matched once.
+    fn derive(&self, rules: &[&Rule]) -> Result<Relations> {
+        let empty = || -> Relations {
+            rules
+                .iter()
+                .map(|rule| (rule.head.predicate.clone(), BTreeSet::new()))
+                .collect()
+        };
+        let mut relations = empty();
+
+        let mut bases = vec![];
+        for rule in rules {
+            let (clauses, calls) = split(&rule.body, &relations)?;
+            bases.push((self.solve(&clauses, vec![HashMap::new()])?, calls));
+        }
+
+        // Nothing has been derived yet, so only the rules that call no
+        // others derive anything in the first round, and they derive
+        // everything they ever will.
+        let mut delta = empty();
+        for (rule, &(ref base, ref calls)) in rules.iter().zip(&bases) {
+            if calls.is_empty() {
+                add_tuples(rule, base, &relations, &mut delta)?;
+            }
+        }
+
+        while delta.values().any(|tuples| !tuples.is_empty()) {
+            for (predicate, tuples) in &delta {
+                if let Some(all) = relations.get_mut(predicate) {
+                    all.extend(tuples.iter().cloned());
+                }
+            }
+
+            let mut next = empty();
+            for (rule, &(ref base, ref calls)) in rules.iter().zip(&bases) {
+                for (i, call) in calls.iter().enumerate() {
+                    if delta[&call.predicate].is_empty() {
+                        continue;
+                    }
+                    // Each derivation of a new tuple uses at least one
+                    // of the last round's, so join each call in turn
+                    // with only those.
+                    let mut bindings = join(call, base.clone(), &delta[&call.predicate]);
+                    for (j, other) in calls.iter().enumerate() {
+                        if j != i {
+                            bindings = join(other, bindings, &relations[&other.predicate]);
+                        }
+                    }
+                    self.check_limits(bindings.len())?;
+                    add_tuples(rule, &bindings, &relations, &mut next)?;
+                }
+            }
+            delta = next;
+        }
+        Ok(relations)
+    }
+}
+
+/// Adds the head of `rule` for each of `bindings` to `new`, unless
+/// it's already in `relations`.
+fn add_tuples(rule: &Rule,
+              bindings: &[Binding],
+              relations: &Relations,
+              new: &mut Relations)
+              -> Result<()> {
+    let predicate = &rule.head.predicate;
+    for binding in bindings {
+        let tuple = head_tuple(rule, binding)?;
+        if !relations[predicate].contains(&tuple) {
+            if let Some(tuples) = new.get_mut(predicate) {
+                tuples.insert(tuple);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The rules that `goals` call, directly or through other rules.
+fn reached<'a>(rules: &'a [Rule], goals: &'a [Atom]) -> Vec<&'a Rule> {
+    let mut reached = HashSet::new();
+    let mut pending = goals.iter().map(|goal| &goal.predicate[..]).collect::<Vec<_>>();
+    while let Some(predicate) = pending.pop() {
+        if !reached.insert(predicate) {
+            continue;
+        }
+        for rule in rules.iter().filter(|rule| rule.head.predicate == predicate) {
+            pending.extend(rule.body.iter().map(|atom| &atom.predicate[..]));
+        }
+    }
+    rules
+        .iter()
+        .filter(|rule| reached.contains(&rule.head.predicate[..]))
+        .collect()
+}
+
+/// Separates atoms that call rules from those that match attributes,
+/// turning the latter into clauses.
+fn split<'a>(atoms: &'a [Atom], relations: &Relations) -> Result<(Vec<Clause>, Vec<&'a Atom>)> {
+    let mut clauses = vec![];
+    let mut calls = vec![];
+    for atom in atoms {
+        if relations.contains_key(&atom.predicate) {
+            calls.push(atom);
+        } else {
+            clauses.push(to_clause(atom)?);
+        }
+    }
+    Ok((clauses, calls))
+}
+
+/// `attr(E, V)`, optionally followed by the transaction and whether
+/// the fact was added, is the clause `(E attr V)`.
+fn to_clause(atom: &Atom) -> Result<Clause> {
+    let args = &atom.args;
+    if args.len() < 2 || args.len() > 4 {
+        return Err(Error::Validation(format!("{} is neither a rule nor an attribute taking 2 to 4 \
+                                              arguments",
+                                             atom.predicate)));
+    }
+
+    let entity = |term: &Term<Value>| match *term {
+        Term::Unbound(ref var) => Ok(Term::Unbound(var.clone())),
+        Term::Bound(ref v) => {
+            v.as_entity().map(Term::Bound).ok_or_else(|| {
+                Error::Validation(format!("{} is not an entity, in {}", v, atom.predicate))
+            })
+        }
+    };
+
+    let mut clause = Clause::new(entity(&args[0])?,
+                                 Term::Bound(atom.predicate.clone()),
+                                 args[1].clone());
+    if let Some(tx) = args.get(2) {
+        clause = clause.with_tx(entity(tx)?);
+    }
+    if let Some(added) = args.get(3) {
+        clause = clause.with_added(added.clone());
+    }
+    Ok(clause)
+}
+
+fn head_tuple(rule: &Rule, binding: &Binding) -> Result<Vec<Value>> {
+    rule.head
+        .args
+        .iter()
+        .map(|arg| match *arg {
+                 Term::Bound(ref v) => Ok(v.clone()),
+                 Term::Unbound(ref var) => {
+                     binding
+                         .get(var)
+                         .cloned()
+                         .ok_or_else(|| {
+                                         Error::Validation(format!("{} is in the head of a {} rule \
+                                                                    but not its body",
+                                                                   var.name,
+                                                                   rule.head.predicate))
+                                     })
+                 }
+             })
+        .collect()
+}
+
+fn join_all(calls: &[&Atom], mut bindings: Vec<Binding>, relations: &Relations) -> Vec<Binding> {
+    for call in calls {
+        bindings = join(call, bindings, &relations[&call.predicate]);
+    }
+    bindings
+}
+
+/// Extends each binding with every tuple that matches `atom`.
+fn join(atom: &Atom, bindings: Vec<Binding>, tuples: &BTreeSet<Vec<Value>>) -> Vec<Binding> {
+    let mut result = vec![];
+    for binding in bindings {
+        'tuples: for tuple in tuples.iter().filter(|t| t.len() == atom.args.len()) {
+            let mut new_binding = binding.clone();
+            for (arg, value) in atom.args.iter().zip(tuple) {
+                match *arg {
+                    Term::Bound(ref v) => {
+                        if !same_value(v, value) {
+                            continue 'tuples;
+                        }
+                    }
+                    Term::Unbound(ref var) => {
+                        if let Some(existing) = new_binding.get(var) {
+                            if !same_value(existing, value) {
+                                continue 'tuples;
+                            }
+                        }
+                        new_binding.insert(var.clone(), value.clone());
+                    }
+                }
+            }
+            result.push(new_binding);
+        }
+    }
+    result
+}
+
+/// Whether two values are equal, counting an entity and its id as
+/// equal.
+fn same_value(a: &Value, b: &Value) -> bool {
+    a == b || (a.as_entity().is_some() && a.as_entity() == b.as_entity())
+}
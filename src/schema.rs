@@ -0,0 +1,208 @@
+//! Value types declared with `db:valueType`, and checking queries
+//! against them before they run.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use db::Db;
+use query::{Query, Term, Var};
+use {Entity, Error, Result, Value};
+
+/// The type of an attribute's values, declared as in
+/// `{db:ident name db:valueType string}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    String,
+    /// A reference to another entity.
+    Ref,
+    Ident,
+    Integer,
+    Float,
+    Boolean,
+    Instant,
+}
+
+impl ValueType {
+    pub fn from_name(name: &str) -> Option<ValueType> {
+        match name {
+            "string" => Some(ValueType::String),
+            "ref" => Some(ValueType::Ref),
+            "ident" => Some(ValueType::Ident),
+            "integer" => Some(ValueType::Integer),
+            "float" => Some(ValueType::Float),
+            "boolean" => Some(ValueType::Boolean),
+            "instant" => Some(ValueType::Instant),
+            _ => None,
+        }
+    }
+
+    /// Whether `value` can be a value of this type. A ref can be
+    /// written as an entity's id or ident.
+    pub fn admits(&self, value: &Value) -> bool {
+        match (*self, value) {
+            (ValueType::Ref, value) if value.as_entity().is_some() => true,
+            (ValueType::String, &Value::String(_)) |
+            (ValueType::Ref, &Value::Ident(_)) |
+            (ValueType::Ident, &Value::Ident(_)) |
+            (ValueType::Integer, &Value::Integer(_)) |
+            (ValueType::Boolean, &Value::Boolean(_)) |
+            (ValueType::Instant, &Value::Timestamp(_)) => true,
+            (ValueType::Float, value) => value.number().is_some(),
+            _ => false,
+        }
+    }
+
+    /// Whether a variable bound to values of this type can stand for
+    /// an entity, as idents can.
+    fn is_entity(&self) -> bool {
+        *self == ValueType::Ref || *self == ValueType::Ident
+    }
+
+    /// Whether values of the two types can be equal, so that a
+    /// variable can be bound by attributes of both.
+    fn joins(&self, other: ValueType) -> bool {
+        let number = |t: ValueType| {
+            t == ValueType::Ref || t == ValueType::Integer || t == ValueType::Float
+        };
+        *self == other || (self.is_entity() && other.is_entity()) ||
+        (number(*self) && number(other))
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            ValueType::String => "a string",
+            ValueType::Ref => "a ref",
+            ValueType::Ident => "an ident",
+            ValueType::Integer => "an integer",
+            ValueType::Float => "a float",
+            ValueType::Boolean => "a boolean",
+            ValueType::Instant => "an instant",
+        })
+    }
+}
+
+impl Db {
+    /// `value` as it's stored as a value of `attr`: integers given
+    /// for a ref are entity ids.
+    pub(crate) fn ref_value(&self, attr: Entity, value: Value) -> Result<Value> {
+        match value {
+            Value::Integer(n) if n >= 0 && self.value_type(attr)? == Some(ValueType::Ref) => {
+                Ok(Value::Entity(Entity(n as u64)))
+            }
+            value => Ok(value),
+        }
+    }
+
+    /// The declared type of `attr`'s values, if it has one.
+    pub fn value_type(&self, attr: Entity) -> Result<Option<ValueType>> {
+        let flag = match self.idents.get_entity("db:valueType".to_string()) {
+            Some(flag) => flag,
+            None => return Ok(None),
+        };
+        match self.attribute_values(attr, flag)?.first() {
+            Some(&Value::Ident(ref name)) => {
+                ValueType::from_name(name)
+                    .map(Some)
+                    .ok_or_else(|| Error::Schema(format!("unknown value type {}", name)))
+            }
+            Some(other) => Err(Error::Schema(format!("unknown value type {}", other))),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks that every attribute `query`'s clauses name exists, and
+    /// that each variable is used consistently with the types of the
+    /// attributes that bind it. Clauses on other sources aren't
+    /// checked, since their schema isn't known until the query runs.
+    pub(crate) fn check_schema(&self, query: &Query) -> Result<()> {
+        let clauses = query.clauses
+            .iter()
+            .enumerate()
+            .map(|(i, clause)| (i + 1, clause))
+            .filter(|&(_, clause)| clause.source.is_none())
+            .collect::<Vec<_>>();
+
+        let mut attrs = HashMap::new();
+        for &(i, clause) in &clauses {
+            if let Term::Bound(ref ident) = clause.attribute {
+                let attr = self.idents
+                    .get_entity(ident.clone())
+                    .ok_or_else(|| {
+                                    Error::Schema(format!("unknown attribute `{}` in clause {}",
+                                                          ident,
+                                                          i))
+                                })?;
+                if let Some(value_type) = self.value_type(attr)? {
+                    attrs.insert(i, (&ident[..], value_type));
+                }
+            }
+        }
+
+        // The type of each variable, from the first clause whose value
+        // it is.
+        let mut types: HashMap<&Var, (ValueType, &str)> = HashMap::new();
+        for &(i, clause) in &clauses {
+            let (attr, value_type) = match attrs.get(&i) {
+                Some(&declared) => declared,
+                None => continue,
+            };
+            match clause.value {
+                Term::Bound(ref value) if !value_type.admits(value) => {
+                    return Err(Error::Validation(format!("{} is not {} (the type of `{}`) in \
+                                                          clause {}",
+                                                         value,
+                                                         value_type,
+                                                         attr,
+                                                         i)))
+                }
+                Term::Bound(_) => {}
+                Term::Unbound(ref var) => {
+                    let known = types.get(var).cloned();
+                    match known {
+                        Some((other, from)) if !other.joins(value_type) => {
+                            return Err(Error::Validation(format!("{} is {} (from `{}`) but {} \
+                                                                  (from `{}`) in clause {}",
+                                                                 var,
+                                                                 other,
+                                                                 from,
+                                                                 value_type,
+                                                                 attr,
+                                                                 i)))
+                        }
+                        Some(_) => {}
+                        None => {
+                            types.insert(var, (value_type, attr));
+                        }
+                    }
+                }
+            }
+        }
+
+        for &(i, clause) in &clauses {
+            let positions = vec![(clause.entity.var(), "entity"),
+                                 (clause.attribute.var(), "attribute"),
+                                 (clause.tx.as_ref().and_then(Term::var), "transaction")];
+            for (var, position) in positions {
+                let var = match var {
+                    Some(var) => var,
+                    None => continue,
+                };
+                match types.get(var) {
+                    Some(&(value_type, from)) if !value_type.is_entity() => {
+                        return Err(Error::Validation(format!("{} is {} (from `{}`) but used in \
+                                                              {} position of clause {}",
+                                                             var,
+                                                             value_type,
+                                                             from,
+                                                             position,
+                                                             i)))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Data sources other than the database a query is run against, which
+//! clauses and tuple patterns name with a `$`, as in
+//! `$old (?e name ?n)` or `$r [?name ?age]`.
+
+use db::Db;
+use query::{Query, Term, TuplePattern};
+use {Binding, QueryResult, Result, Value};
+
+/// Something a query's clauses can be matched against, given to
+/// `PreparedQuery::execute_with_sources` under the name the query
+/// declares it with.
+#[derive(Clone, Copy)]
+pub enum Source<'a> {
+    /// Another database, or the same one at another time, matched
+    /// with clauses such as `$old (?e name ?n)`.
+    Db(&'a Db),
+    /// Tuples of values, matched by position with tuple patterns:
+    /// `$r [?a ?b]` matches each tuple of two values.
+    Relation(&'a [Vec<Value>]),
+}
+
+impl Db {
+    /// Runs `query` with the given sources, e.g.
+    /// `&[("$old", Source::Db(&old_db))]`. Clauses without a source
+    /// are matched against this `Db`.
+    pub fn query_with_sources(&self,
+                              query: &Query,
+                              sources: &[(&str, Source)])
+                              -> Result<QueryResult> {
+        self.prepare(query)?.execute_with_sources(self, sources, &[])
+    }
+}
+
+/// Extends each of `bindings` with every tuple of `relation` that
+/// matches `pattern`.
+pub(crate) fn match_tuples(pattern: &TuplePattern,
+                           relation: &[Vec<Value>],
+                           bindings: Vec<Binding>)
+                           -> Vec<Binding> {
+    let mut new_bindings = vec![];
+    for binding in bindings {
+        for tuple in relation.iter().filter(|tuple| tuple.len() == pattern.terms.len()) {
+            if let Some(new_binding) = unify_tuple(&binding, &pattern.terms, tuple) {
+                new_bindings.push(new_binding);
+            }
+        }
+    }
+    new_bindings
+}
+
+fn unify_tuple(binding: &Binding, terms: &[Term<Value>], tuple: &[Value]) -> Option<Binding> {
+    let mut new_binding = binding.clone();
+    for (term, value) in terms.iter().zip(tuple) {
+        match *term {
+            Term::Bound(ref constant) if constant != value => return None,
+            Term::Bound(_) => {}
+            Term::Unbound(ref var) => {
+                if let Some(existing) = new_binding.get(var) {
+                    if existing != value {
+                        return None;
+                    }
+                    continue;
+                }
+                new_binding.insert(var.clone(), value.clone());
+            }
+        }
+    }
+    Some(new_binding)
+}
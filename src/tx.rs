@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use chrono::prelude::UTC;
@@ -5,10 +6,10 @@ use chrono::prelude::UTC;
 use backends::KVStore;
 use btree::IndexNode;
 use db::{add_node, Db, DbContents};
-use {Tx, TxReport, Entity, Record, Value, TxItem, Result, IdentMap};
+use fulltext;
+use {Tx, TxReport, Entity, Record, Value, TxItem, Error, Result, IdentMap};
 
 pub struct Transactor {
-    next_id: u64,
     current_db: Db
 }
 
@@ -17,60 +18,97 @@ impl Transactor {
         let contents: DbContents = store.get_contents()?;
 
         Ok(Transactor {
-            next_id: contents.next_id,
             current_db: Db::new(contents, store.clone())
         })
     }
 
     pub fn process_tx(&mut self, tx: Tx) -> Result<TxReport> {
-        let mut new_entities = vec![];
-        let tx_entity = Entity(self.get_id());
-        let attr = self.current_db.idents.get_entity("db:txInstant".to_string()).unwrap();
-        let mut db_after = add(&self.current_db,
-                               Record::addition(tx_entity, attr, Value::Timestamp(UTC::now()), tx_entity))?;
-        for item in tx.items {
-            match item {
-                TxItem::Addition(f) => {
-                    let attr = match db_after.idents.get_entity(f.attribute) {
-                        Some(attr) => attr,
-                        None => return Ok(TxReport::Failure("invalid attribute".into()))
-                    };
-                    db_after = add(&db_after, Record::addition(f.entity, attr, f.value, tx_entity))?;
-                }
-                TxItem::NewEntity(ht) => {
-                    let entity = Entity(self.get_id());
-                    for (k, v) in ht {
-                        let attr = match db_after.idents.get_entity(k) {
-                            Some(attr) => attr,
-                            None => return Ok(TxReport::Failure("invalid attribute".into()))
-                        };
-
-                        db_after = add(&db_after, Record::addition(entity, attr, v, tx_entity))?;
-                    }
-                    new_entities.push(entity);
-                }
-                TxItem::Retraction(f) => {
-                    let attr = match db_after.idents.get_entity(f.attribute) {
-                        Some(attr) => attr,
-                        None => return Ok(TxReport::Failure("invalid attribute".into()))
-                    };
-                    db_after = add(&db_after, Record::retraction(f.entity, attr, f.value, tx_entity))?;
+        let (db_after, report) = apply(&self.current_db, tx)?;
+        if let TxReport::Success { .. } = report {
+            save_contents(&db_after)?;
+            self.current_db = db_after;
+        }
+        Ok(report)
+    }
+}
+
+/// Applies `tx` to `db`, returning the database as it would be
+/// afterwards.  Nothing is saved to the db's contents, so the result
+/// is only visible through the returned `Db`, although the new index
+/// nodes are still written to `db`'s store.
+pub fn apply(db: &Db, tx: Tx) -> Result<(Db, TxReport)> {
+    match apply_items(db, tx) {
+        Ok(result) => Ok(result),
+        // Problems with the transaction itself are reported back to
+        // the client; anything else is a problem with the database.
+        Err(e @ Error::Schema(_)) |
+        Err(e @ Error::Validation(_)) |
+        Err(e @ Error::Conflict(_)) => Ok((db.clone(), TxReport::Failure(e))),
+        Err(e) => Err(e),
+    }
+}
+
+fn apply_items(db: &Db, tx: Tx) -> Result<(Db, TxReport)> {
+    let mut new_entities = vec![];
+    let mut db_after = db.clone();
+    let tx_entity = Entity(get_id(&mut db_after));
+    let attr = attribute(&db_after, "db:txInstant".to_string())?;
+    let mut fulltext_attrs = fulltext::attributes(&db_after)?;
+    db_after = add(&db_after,
+                   Record::addition(tx_entity, attr, Value::Timestamp(UTC::now()), tx_entity),
+                   &mut fulltext_attrs)?;
+    for item in tx.items {
+        match item {
+            TxItem::Addition(f) => {
+                let attr = attribute(&db_after, f.attribute)?;
+                let value = db_after.ref_value(attr, f.value)?;
+                db_after = add(&db_after,
+                               Record::addition(f.entity, attr, value, tx_entity),
+                               &mut fulltext_attrs)?;
+            }
+            TxItem::NewEntity(ht) => {
+                let entity = Entity(get_id(&mut db_after));
+                for (k, v) in ht {
+                    let attr = attribute(&db_after, k)?;
+                    let value = db_after.ref_value(attr, v)?;
+                    db_after = add(&db_after,
+                                   Record::addition(entity, attr, value, tx_entity),
+                                   &mut fulltext_attrs)?;
                 }
+                new_entities.push(entity);
+            }
+            TxItem::Retraction(f) => {
+                let attr = attribute(&db_after, f.attribute)?;
+                // Retract the value as it's stored, which may be an
+                // entity given here by its id or ident.
+                let value = db_after.ref_value(attr, f.value)?;
+                let value = db_after.attribute_values(f.entity, attr)?
+                    .into_iter()
+                    .find(|stored| db_after.idents.same_value(&value, stored))
+                    .unwrap_or(value);
+                db_after = add(&db_after,
+                               Record::retraction(f.entity, attr, value, tx_entity),
+                               &mut fulltext_attrs)?;
             }
         }
-
-        save_contents(&db_after, self.next_id)?;
-        self.current_db = db_after;
-        Ok(TxReport::Success { new_entities })
     }
 
-    fn get_id(&mut self) -> u64 {
-        let id = self.next_id;
-        self.next_id +=1 ;
-        id
+    Ok((db_after, TxReport::Success { tx: tx_entity, new_entities }))
+}
+
+fn attribute(db: &Db, ident: String) -> Result<Entity> {
+    match db.idents.get_entity(ident.clone()) {
+        Some(attr) => Ok(attr),
+        None => Err(Error::Schema(format!("unknown attribute {}", ident))),
     }
 }
 
+fn get_id(db: &mut Db) -> u64 {
+    let id = db.next_id;
+    db.next_id += 1;
+    id
+}
+
 pub fn create_db(store: Arc<KVStore>) -> Result<()> {
     let empty_root: IndexNode<Record> = IndexNode::Leaf { items: vec![] };
 
@@ -84,6 +122,7 @@ pub fn create_db(store: Arc<KVStore>) -> Result<()> {
         eav: eav_root,
         ave: ave_root,
         aev: aev_root,
+        fulltext: None,
     };
 
     let mut db = Db::new(contents, store.clone());
@@ -92,64 +131,95 @@ pub fn create_db(store: Arc<KVStore>) -> Result<()> {
     // Bootstrap some attributes we need to run transactions,
     // because they need to reference one another.
 
+    let mut fulltext_attrs = HashSet::new();
+
     // Initial transaction entity
     db = add(&db, Record::addition(Entity(0),
                             Entity(2),
                             Value::Timestamp(UTC::now()),
-                            Entity(0)))?;
+                            Entity(0)), &mut fulltext_attrs)?;
 
     // Entity for the db:ident attribute
     db = add(&db, Record::addition(Entity(1),
                             Entity(1),
                             Value::Ident("db:ident".into()),
-                            Entity(0)))?;
+                            Entity(0)), &mut fulltext_attrs)?;
 
     // Entity for the db:txInstant attribute
     db = add(&db, Record::addition(Entity(2),
                             Entity(1),
                             Value::Ident("db:txInstant".into()),
-                            Entity(0)))?;
+                            Entity(0)), &mut fulltext_attrs)?;
 
-    save_contents(&db, 3)?;
+    db.next_id = 3;
+    save_contents(&db)?;
     Ok(())
 }
 
 /// Saves the db metadata (index root nodes, entity ID state) to
 /// storage, when implemented by the storage backend (i.e. when
 /// not using in-memory storage).
-fn save_contents(db: &Db, next_id: u64) -> Result<()> {
+fn save_contents(db: &Db) -> Result<()> {
     let contents = DbContents {
-        next_id,
+        next_id: db.next_id,
         idents: db.idents.clone(),
         eav: db.eav.root_ref.clone(),
         aev: db.aev.root_ref.clone(),
         ave: db.ave.root_ref.clone(),
+        fulltext: db.fulltext.as_ref().map(|index| index.root_ref.clone()),
     };
 
     db.store.set_contents(&contents)?;
     Ok(())
 }
 
-fn add(db: &Db, record: Record) -> Result<Db> {
-    let new_eav = db.eav.insert(record.clone())?;
-    let new_ave = db.ave.insert(record.clone())?;
-    let new_aev = db.aev.insert(record.clone())?;
-
+/// Adds `record` to `db`. `fulltext_attrs` holds the attributes marked
+/// `db:fulltext`, and is kept up to date as records mark them.
+fn add(db: &Db, record: Record, fulltext_attrs: &mut HashSet<Entity>) -> Result<Db> {
     // If the record has a db:ident, we need to add it to the ident map.
-    let new_idents = if record.attribute == db.idents.get_entity("db:ident".to_string()).unwrap() {
+    let new_idents = if Some(record.attribute) == db.idents.get_entity("db:ident".to_string()) {
         match record.value {
-            Value::Ident(s) => db.idents.add(s.clone(), record.entity),
-            _ => unimplemented!(), // FIXME: type error
+            Value::Ident(ref s) if record.retracted => db.idents.retract(s, record.entity),
+            Value::Ident(ref s) if db.idents.get_entity(s.clone()) == Some(record.entity) => {
+                db.idents.clone()
+            }
+            Value::Ident(ref s) => db.idents.add(s.clone(), record.entity),
+            ref v => {
+                return Err(Error::Validation(format!("db:ident must be an ident, not {}", v)))
+            }
         }
     } else {
         db.idents.clone()
     };
 
+    let new_eav = db.eav.insert(record.clone())?;
+    let new_ave = db.ave.insert(record.clone())?;
+    let new_aev = db.aev.insert(record.clone())?;
+    let mut new_fulltext = db.fulltext.clone();
+    if fulltext_attrs.contains(&record.attribute) {
+        new_fulltext = fulltext::index(db, new_fulltext, &record)?;
+    }
+    // Marking an attribute db:fulltext indexes the values it
+    // already has.
+    if Some(record.attribute) == db.idents.get_entity("db:fulltext".to_string()) &&
+       record.value == Value::Boolean(true) {
+        if record.retracted {
+            fulltext_attrs.remove(&record.entity);
+        } else if fulltext_attrs.insert(record.entity) {
+            new_fulltext = fulltext::index_attribute(db, new_fulltext, record.entity)?;
+        }
+    }
+
     Ok(Db {
         eav: new_eav,
         ave: new_ave,
         aev: new_aev,
+        fulltext: new_fulltext,
         idents: new_idents,
-        store: db.store.clone()
+        store: db.store.clone(),
+        view: db.view,
+        next_id: db.next_id,
+        cache: db.cache.clone(),
+        limits: db.limits.clone(),
     })
 }